@@ -0,0 +1,341 @@
+//! Bitmap allocation.
+//!
+//! A [`Bitmap`] allocator divides a region of memory in granules of a fixed
+//! size, and tracks which granules are used with one bit each. Blocks are
+//! runs of contiguous granules, found by scanning the bitmap a word at a
+//! time.
+//!
+//! [`Bitmap`]: struct.Bitmap.html
+
+use core::cmp;
+use core::fmt;
+use core::mem;
+use core::ptr::{self, NonNull};
+
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout};
+use crate::combinators::Owns;
+
+const BITS: usize = usize::BITS as usize;
+
+/// Returns the number of words needed for a bitmap of `granules` bits.
+fn words_for(granules: usize) -> usize {
+    (granules + BITS - 1) >> BITS.trailing_zeros()
+}
+
+/// A bitmap allocator over a contiguous region, handing out runs of
+/// `GRANULE`-byte granules.
+///
+/// Requests are rounded up to a whole number of granules, and the first run
+/// of free granules large enough, and suitably aligned, is used. Alignments
+/// larger than a granule are satisfied by only considering runs starting at
+/// an aligned granule, wherever the region is placed. `grow_in_place` succeeds when
+/// the granules following the block are free.
+///
+/// The bitmap is taken from the start of the region.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::alloc::{AllocRef, Layout};
+/// use allocator_api::bitmap::Bitmap;
+/// use allocator_api::Global;
+///
+/// let mut slots: Bitmap<64> = Bitmap::from_alloc(&mut Global, 64 * 1024).unwrap();
+/// let layout = Layout::from_size_align(100, 64).unwrap();
+/// let (ptr, size) = slots.alloc(layout).unwrap();
+/// assert_eq!(size, 128);
+/// assert_eq!(slots.usage().used_granules, 2);
+/// unsafe {
+///     assert_eq!(slots.grow_in_place(ptr, layout, 200), Ok(256));
+///     slots.dealloc(ptr, Layout::from_size_align(256, 64).unwrap());
+///     slots.free_region(&mut Global);
+/// }
+/// # }
+/// ```
+pub struct Bitmap<const GRANULE: usize> {
+    region: NonNull<u8>,
+    layout: Layout,
+    /// The first granule.
+    base: NonNull<u8>,
+    granules: usize,
+    used: usize,
+}
+
+/// A report of the granules used in a [`Bitmap`] allocator.
+///
+/// [`Bitmap`]: struct.Bitmap.html
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Usage {
+    /// Number of granules managed by the allocator.
+    pub granules: usize,
+    /// Number of granules currently allocated.
+    pub used_granules: usize,
+    /// Number of granules in the longest run of free granules.
+    pub largest_free_run: usize,
+}
+
+// The allocator owns the region.
+unsafe impl<const GRANULE: usize> Send for Bitmap<GRANULE> {}
+
+impl<const GRANULE: usize> Bitmap<GRANULE> {
+    /// Creates an allocator managing the `len` bytes starting at `region`.
+    ///
+    /// # Safety
+    ///
+    /// The region must be valid for reads and writes, and not be used by
+    /// anything else, for as long as the allocator and the blocks it
+    /// allocated are used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `GRANULE` is not a power of two, if the region is not
+    /// aligned for a `usize`, or if it is too small to hold a granule after
+    /// the bitmap.
+    pub unsafe fn new(region: NonNull<u8>, len: usize) -> Self {
+        let align = 1 << cmp::min((region.as_ptr() as usize).trailing_zeros(), 31);
+        Self::with_layout(region, Layout::from_size_align_unchecked(len, align))
+    }
+
+    /// Allocates a region of `len` bytes from `a`, and creates an allocator
+    /// managing it.
+    ///
+    /// The region is aligned to `GRANULE`, or to a `usize` if that is
+    /// larger. It must eventually be given back to `a` with
+    /// [`free_region`].
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`new`].
+    ///
+    /// [`free_region`]: #method.free_region
+    /// [`new`]: #method.new
+    pub fn from_alloc<A: AllocRef>(a: &mut A, len: usize) -> Result<Self, AllocErr> {
+        let align = cmp::max(GRANULE, mem::align_of::<usize>());
+        let layout = Layout::from_size_align(len, align).map_err(|_| AllocErr)?;
+        let (region, _) = a.alloc(layout)?;
+        Ok(unsafe { Self::with_layout(region, layout) })
+    }
+
+    /// Gives back the region to the allocator it was obtained from with
+    /// [`from_alloc`].
+    ///
+    /// # Safety
+    ///
+    /// `a` must be the allocator given to [`from_alloc`], and the blocks
+    /// allocated from this allocator must not be used anymore.
+    ///
+    /// [`from_alloc`]: #method.from_alloc
+    pub unsafe fn free_region<A: AllocRef>(self, a: &mut A) {
+        a.dealloc(self.region, self.layout)
+    }
+
+    unsafe fn with_layout(region: NonNull<u8>, layout: Layout) -> Self {
+        assert!(GRANULE.is_power_of_two(), "GRANULE must be a power of two");
+        assert!(
+            layout.align() >= mem::align_of::<usize>(),
+            "region not aligned for a usize"
+        );
+        // The bitmap is sized for all the granules of the region, which is a
+        // few bits more than needed.
+        let words = words_for(layout.size() / GRANULE);
+        let reserved = (words * mem::size_of::<usize>() + GRANULE - 1) & !(GRANULE - 1);
+        assert!(
+            reserved < layout.size() && layout.size() - reserved >= GRANULE,
+            "region too small for its bitmap"
+        );
+        ptr::write_bytes(region.as_ptr().cast::<usize>(), 0, words);
+        Bitmap {
+            region,
+            layout,
+            base: NonNull::new_unchecked(region.as_ptr().add(reserved)),
+            granules: (layout.size() - reserved) / GRANULE,
+            used: 0,
+        }
+    }
+
+    /// Returns a report of the granules in use.
+    ///
+    /// Finding the longest free run scans the whole bitmap.
+    pub fn usage(&self) -> Usage {
+        let mut largest_free_run = 0;
+        let mut start = 0;
+        while let Some(free) = self.find_clear(start) {
+            let end = self.find_set(free, self.granules).unwrap_or(self.granules);
+            largest_free_run = cmp::max(largest_free_run, end - free);
+            start = end;
+        }
+        Usage {
+            granules: self.granules,
+            used_granules: self.used,
+            largest_free_run,
+        }
+    }
+
+    fn words(&self) -> &[usize] {
+        let len = words_for(self.granules);
+        unsafe { core::slice::from_raw_parts(self.region.as_ptr().cast(), len) }
+    }
+
+    fn words_mut(&mut self) -> &mut [usize] {
+        let len = words_for(self.granules);
+        unsafe { core::slice::from_raw_parts_mut(self.region.as_ptr().cast(), len) }
+    }
+
+    /// Returns the index of the first free granule at or after `from`.
+    fn find_clear(&self, from: usize) -> Option<usize> {
+        let words = self.words();
+        let mut i = from / BITS;
+        let mut mask = !0usize << (from % BITS);
+        while i < words.len() {
+            let clear = !words[i] & mask;
+            if clear != 0 {
+                let index = i * BITS + clear.trailing_zeros() as usize;
+                return if index < self.granules { Some(index) } else { None };
+            }
+            i += 1;
+            mask = !0;
+        }
+        None
+    }
+
+    /// Returns the index of the first used granule in `from..to`.
+    fn find_set(&self, from: usize, to: usize) -> Option<usize> {
+        let words = self.words();
+        let mut i = from / BITS;
+        let mut mask = !0usize << (from % BITS);
+        while i * BITS < to {
+            let set = words[i] & mask;
+            if set != 0 {
+                let index = i * BITS + set.trailing_zeros() as usize;
+                return if index < to { Some(index) } else { None };
+            }
+            i += 1;
+            mask = !0;
+        }
+        None
+    }
+
+    /// Marks the granules in `from..to` used or free.
+    fn set_range(&mut self, from: usize, to: usize, used: bool) {
+        let words = self.words_mut();
+        let mut i = from;
+        while i < to {
+            let bits = cmp::min(to - i, BITS - i % BITS);
+            let mask = (!0usize >> (BITS - bits)) << (i % BITS);
+            if used {
+                words[i / BITS] |= mask;
+            } else {
+                words[i / BITS] &= !mask;
+            }
+            i += bits;
+        }
+        if used {
+            self.used += to - from;
+        } else {
+            self.used -= to - from;
+        }
+    }
+
+    /// Returns the number of granules needed for `size` bytes.
+    fn granules_for(size: usize) -> usize {
+        cmp::max(size / GRANULE + (size & (GRANULE - 1) != 0) as usize, 1)
+    }
+
+    fn index_of(&self, ptr: NonNull<u8>) -> usize {
+        (ptr.as_ptr() as usize - self.base.as_ptr() as usize) / GRANULE
+    }
+}
+
+impl<const GRANULE: usize> fmt::Debug for Bitmap<GRANULE> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bitmap")
+            .field("base", &self.base)
+            .field("granules", &self.granules)
+            .field("used", &self.used)
+            .finish()
+    }
+}
+
+unsafe impl<const GRANULE: usize> AllocRef for Bitmap<GRANULE> {
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let align = layout.align();
+        // Aligned blocks start at the first aligned granule, and then every
+        // `align / GRANULE` granules. When no granule is aligned, which can
+        // only happen when the base is less aligned than `GRANULE`, there is
+        // no such block.
+        let offset = align.wrapping_sub(self.base.as_ptr() as usize) & (align - 1);
+        if offset & (GRANULE - 1) != 0 {
+            return Err(AllocErr);
+        }
+        let first = offset / GRANULE;
+        let step = cmp::max(align / GRANULE, 1);
+        let count = Self::granules_for(layout.size());
+        let mut start = first;
+        loop {
+            start = self.find_clear(start).ok_or(AllocErr)?;
+            start = first + ((start - first + step - 1) & !(step - 1));
+            let end = start.checked_add(count).ok_or(AllocErr)?;
+            if end > self.granules {
+                return Err(AllocErr);
+            }
+            match self.find_set(start, end) {
+                Some(used) => start = used + 1,
+                None => {
+                    self.set_range(start, end, true);
+                    let ptr = unsafe { NonNull::new_unchecked(self.base.as_ptr().add(start * GRANULE)) };
+                    return Ok((ptr, count * GRANULE));
+                }
+            }
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let start = self.index_of(ptr);
+        self.set_range(start, start + Self::granules_for(layout.size()), false)
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        (layout.size(), Self::granules_for(layout.size()) * GRANULE)
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let start = self.index_of(ptr);
+        let end = start + Self::granules_for(layout.size());
+        let new_end = start + Self::granules_for(new_size);
+        if new_end > self.granules || self.find_set(end, new_end).is_some() {
+            return Err(CannotReallocInPlace);
+        }
+        self.set_range(end, new_end, true);
+        Ok((new_end - start) * GRANULE)
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let start = self.index_of(ptr);
+        let end = start + Self::granules_for(layout.size());
+        let new_end = start + Self::granules_for(new_size);
+        self.set_range(new_end, end, false);
+        Ok((new_end - start) * GRANULE)
+    }
+}
+
+impl<const GRANULE: usize> Owns for Bitmap<GRANULE> {
+    fn owns(&self, ptr: NonNull<u8>, _layout: Layout) -> bool {
+        let start = self.base.as_ptr() as usize;
+        (start..start + self.granules * GRANULE).contains(&(ptr.as_ptr() as usize))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests;
@@ -0,0 +1,104 @@
+use super::*;
+use crate::alloc::Global;
+
+fn layout(size: usize, align: usize) -> Layout {
+    Layout::from_size_align(size, align).unwrap()
+}
+
+#[test]
+fn bitmap_scans_words() {
+    let mut bitmap: Bitmap<16> = Bitmap::from_alloc(&mut Global, 16 * 1024).unwrap();
+    let granules = bitmap.usage().granules;
+    // The bitmap of 1024 granules takes 128 bytes, that is 8 granules.
+    assert_eq!(granules, 1016);
+    assert_eq!(bitmap.usage().largest_free_run, granules);
+
+    // Fill most of the first word, and straddle the second one.
+    let (a, size) = bitmap.alloc(layout(60 * 16, 16)).unwrap();
+    assert_eq!(size, 60 * 16);
+    assert_eq!(bitmap.usable_size(&layout(60 * 16 - 5, 16)), (60 * 16 - 5, 60 * 16));
+    let (b, _) = bitmap.alloc(layout(10 * 16, 16)).unwrap();
+    assert_eq!(bitmap.index_of(b), 60);
+    let (c, _) = bitmap.alloc(layout(1, 1)).unwrap();
+    assert_eq!(bitmap.index_of(c), 70);
+    unsafe { bitmap.dealloc(a, layout(60 * 16, 16)) };
+    assert_eq!(
+        bitmap.usage(),
+        Usage {
+            granules,
+            used_granules: 11,
+            largest_free_run: granules - 71,
+        }
+    );
+
+    // Too large for the hole left by `a`.
+    let (d, _) = bitmap.alloc(layout(61 * 16, 16)).unwrap();
+    assert_eq!(bitmap.index_of(d), 71);
+    // Aligned to 8 granules, in the hole left by `a`.
+    let (e, _) = bitmap.alloc(layout(16, 128)).unwrap();
+    assert_eq!(e.as_ptr() as usize % 128, 0);
+    assert!(bitmap.index_of(e) < 8);
+    let (f, _) = bitmap.alloc(layout(16, 128)).unwrap();
+    assert_eq!(bitmap.index_of(f), bitmap.index_of(e) + 8);
+    assert_eq!(bitmap.alloc(layout(granules * 16, 16)), Err(AllocErr));
+
+    unsafe {
+        for &(ptr, size) in &[(b, 160), (c, 1), (d, 61 * 16), (e, 16), (f, 16)] {
+            bitmap.dealloc(ptr, layout(size, 16));
+        }
+        assert_eq!(bitmap.usage().used_granules, 0);
+        bitmap.free_region(&mut Global);
+    }
+}
+
+#[test]
+fn bitmap_aligns_within_region() {
+    // A region 16 bytes past a page boundary, whose base after the 32-byte
+    // bitmap is 48 bytes past it.
+    let region_layout = layout(4096, 4096);
+    let (page, _) = Global.alloc(region_layout).unwrap();
+    unsafe {
+        let region = NonNull::new_unchecked(page.as_ptr().add(16));
+        let mut bitmap: Bitmap<16> = Bitmap::new(region, 4096 - 16);
+        let (a, _) = bitmap.alloc(layout(16, 8)).unwrap();
+        assert_eq!(bitmap.index_of(a), 0);
+        let (b, _) = bitmap.alloc(layout(16, 128)).unwrap();
+        assert_eq!(b.as_ptr().offset_from(page.as_ptr()), 128);
+        let (c, _) = bitmap.alloc(layout(16, 128)).unwrap();
+        assert_eq!(c.as_ptr().offset_from(page.as_ptr()), 256);
+        let (d, _) = bitmap.alloc(layout(1024, 1024)).unwrap();
+        assert_eq!(d.as_ptr().offset_from(page.as_ptr()), 1024);
+        assert_eq!(bitmap.alloc(layout(16, 4096)), Err(AllocErr));
+
+        // With granules larger than the alignment of the base, no granule is
+        // aligned to them.
+        let mut bitmap: Bitmap<32> = Bitmap::new(region, 4096 - 16);
+        assert_eq!(bitmap.alloc(layout(32, 32)), Err(AllocErr));
+        let (e, _) = bitmap.alloc(layout(32, 16)).unwrap();
+        assert_eq!(bitmap.index_of(e), 0);
+
+        Global.dealloc(page, region_layout);
+    }
+}
+
+#[test]
+fn bitmap_resizes_in_place() {
+    let mut bitmap: Bitmap<32> = Bitmap::from_alloc(&mut Global, 4096).unwrap();
+    unsafe {
+        let (a, _) = bitmap.alloc(layout(32, 32)).unwrap();
+        let (b, _) = bitmap.alloc(layout(32, 32)).unwrap();
+        assert_eq!(bitmap.grow_in_place(a, layout(32, 32), 64), Err(CannotReallocInPlace));
+        assert_eq!(bitmap.grow_in_place(b, layout(32, 32), 100), Ok(128));
+        assert_eq!(bitmap.usage().used_granules, 5);
+        assert_eq!(bitmap.shrink_in_place(b, layout(100, 32), 33), Ok(64));
+        assert_eq!(bitmap.usage().used_granules, 3);
+
+        b.as_ptr().write_bytes(0x42, 33);
+        bitmap.dealloc(b, layout(64, 32));
+        let (c, size) = bitmap.realloc(a, layout(32, 32), 1000).unwrap();
+        assert_eq!((c, size), (a, 1024));
+        bitmap.dealloc(c, layout(1000, 32));
+        assert_eq!(bitmap.usage().used_granules, 0);
+        bitmap.free_region(&mut Global);
+    }
+}
@@ -0,0 +1,354 @@
+//! Buddy allocation.
+//!
+//! A [`Buddy`] allocator manages a contiguous region of memory as blocks of
+//! power-of-two sizes. Each block of order `k` (of size `2^k`) can be split
+//! into two *buddies* of order `k - 1`, which are merged back when both are
+//! free, keeping fragmentation in check with cheap bookkeeping.
+//!
+//! [`Buddy`]: struct.Buddy.html
+
+use core::cmp;
+use core::fmt;
+use core::mem;
+use core::ptr::{self, NonNull};
+
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout};
+use crate::combinators::Owns;
+
+struct FreeNode {
+    prev: Option<NonNull<FreeNode>>,
+    next: Option<NonNull<FreeNode>>,
+}
+
+const ORDERS: usize = usize::BITS as usize;
+
+/// A buddy allocator over a contiguous region.
+///
+/// Blocks have sizes from `2^MIN_ORDER` to `2^MAX_ORDER` bytes. Requests
+/// are rounded up to the next power of two, and to at least their
+/// alignment. Blocks are aligned to their size relative to the start of the
+/// region, so alignments larger than the alignment of the region itself
+/// are not supported.
+///
+/// The bookkeeping needs one byte per block of the minimal size, which is
+/// taken from the start of the region. Free blocks are kept in intrusive
+/// linked lists, so `MIN_ORDER` must be large enough for a block to hold two
+/// pointers.
+///
+/// `grow_in_place` succeeds when the following buddies are free, merging
+/// them with the block, and `shrink_in_place` splits the block, freeing the
+/// upper halves. Consequently, `realloc` only copies data when the block
+/// can't grow in place.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::buddy::Buddy;
+/// use allocator_api::{Global, RawVec};
+///
+/// let mut buddy: Buddy<6, 16> = Buddy::from_alloc(&mut Global, 1 << 20).unwrap();
+/// {
+///     let mut v: RawVec<u8, _> = RawVec::with_capacity_in(100, &mut buddy);
+///     assert_eq!(v.capacity(), 128);
+///     let ptr = v.ptr();
+///     v.reserve(128, 100);
+///     assert_eq!(v.capacity(), 256);
+///     assert_eq!(v.ptr(), ptr);
+/// }
+/// unsafe { buddy.free_region(&mut Global) };
+/// # }
+/// ```
+pub struct Buddy<const MIN_ORDER: usize, const MAX_ORDER: usize> {
+    region: NonNull<u8>,
+    layout: Layout,
+    /// For each block of the minimal size, the order of the free block
+    /// starting there plus one, or 0 if there is none.
+    orders: NonNull<u8>,
+    heads: [Option<NonNull<FreeNode>>; ORDERS],
+    free_bytes: usize,
+}
+
+// The allocator owns the region.
+unsafe impl<const MIN_ORDER: usize, const MAX_ORDER: usize> Send for Buddy<MIN_ORDER, MAX_ORDER> {}
+
+impl<const MIN_ORDER: usize, const MAX_ORDER: usize> Buddy<MIN_ORDER, MAX_ORDER> {
+    /// Creates an allocator managing the `len` bytes starting at `region`.
+    ///
+    /// # Safety
+    ///
+    /// The region must be valid for reads and writes, and not be used by
+    /// anything else, for as long as the allocator and the blocks it
+    /// allocated are used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `MIN_ORDER` is too small for a block to hold two pointers,
+    /// if `MAX_ORDER` is smaller than `MIN_ORDER` or doesn't fit in a
+    /// `usize`, or if the region is too small to hold the bookkeeping.
+    pub unsafe fn new(region: NonNull<u8>, len: usize) -> Self {
+        let align = 1 << cmp::min((region.as_ptr() as usize).trailing_zeros(), 31);
+        Self::with_layout(region, Layout::from_size_align_unchecked(len, align))
+    }
+
+    /// Allocates a region of `len` bytes from `a`, and creates an allocator
+    /// managing it.
+    ///
+    /// The region is aligned to `2^MAX_ORDER`, up to 4096 bytes. It must
+    /// eventually be given back to `a` with [`free_region`].
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`new`].
+    ///
+    /// [`free_region`]: #method.free_region
+    /// [`new`]: #method.new
+    pub fn from_alloc<A: AllocRef>(a: &mut A, len: usize) -> Result<Self, AllocErr> {
+        let align = 1 << cmp::min(MAX_ORDER, 12);
+        let layout = Layout::from_size_align(len, align).map_err(|_| AllocErr)?;
+        let (region, _) = a.alloc(layout)?;
+        Ok(unsafe { Self::with_layout(region, layout) })
+    }
+
+    /// Gives back the region to the allocator it was obtained from with
+    /// [`from_alloc`].
+    ///
+    /// # Safety
+    ///
+    /// `a` must be the allocator given to [`from_alloc`], and the blocks
+    /// allocated from this allocator must not be used anymore.
+    ///
+    /// [`from_alloc`]: #method.from_alloc
+    pub unsafe fn free_region<A: AllocRef>(self, a: &mut A) {
+        a.dealloc(self.region, self.layout)
+    }
+
+    unsafe fn with_layout(region: NonNull<u8>, layout: Layout) -> Self {
+        assert!(
+            1 << MIN_ORDER >= mem::size_of::<FreeNode>(),
+            "MIN_ORDER is too small to hold free list nodes"
+        );
+        assert!(
+            MIN_ORDER <= MAX_ORDER && MAX_ORDER < ORDERS,
+            "MAX_ORDER must be at least MIN_ORDER, and less than usize::BITS"
+        );
+        let granules = layout.size() >> MIN_ORDER;
+        let reserved = (granules + (1 << MIN_ORDER) - 1) >> MIN_ORDER << MIN_ORDER;
+        assert!(reserved < layout.size(), "region too small for its bookkeeping");
+        ptr::write_bytes(region.as_ptr(), 0, granules);
+        let mut buddy = Buddy {
+            region,
+            layout,
+            orders: region,
+            heads: [None; ORDERS],
+            free_bytes: 0,
+        };
+        // Cover the rest of the region with the largest blocks possible.
+        let mut offset = reserved;
+        loop {
+            let order = (MIN_ORDER..=MAX_ORDER)
+                .rev()
+                .find(|&order| offset & ((1 << order) - 1) == 0 && offset + (1 << order) <= layout.size());
+            match order {
+                Some(order) => {
+                    buddy.push(offset, order);
+                    offset += 1 << order;
+                }
+                None => break,
+            }
+        }
+        buddy
+    }
+
+    /// Returns the number of bytes in free blocks.
+    pub fn free_bytes(&self) -> usize {
+        self.free_bytes
+    }
+
+    /// Returns the size of the largest block that can currently be
+    /// allocated, or `None` if there is no free block.
+    pub fn largest_free_block(&self) -> Option<usize> {
+        (MIN_ORDER..=MAX_ORDER)
+            .rev()
+            .find(|&order| self.heads[order].is_some())
+            .map(|order| 1 << order)
+    }
+
+    /// Returns the order of the block for the given layout.
+    fn order_for(&self, layout: Layout) -> Option<usize> {
+        if layout.align() > self.layout.align() {
+            return None;
+        }
+        let size = cmp::max(cmp::max(layout.size(), layout.align()), 1 << MIN_ORDER);
+        let order = size.checked_next_power_of_two()?.trailing_zeros() as usize;
+        if order > MAX_ORDER {
+            None
+        } else {
+            Some(order)
+        }
+    }
+
+    fn offset_of(&self, ptr: NonNull<u8>) -> usize {
+        ptr.as_ptr() as usize - self.region.as_ptr() as usize
+    }
+
+    fn node(&self, offset: usize) -> NonNull<FreeNode> {
+        unsafe { NonNull::new_unchecked(self.region.as_ptr().add(offset)).cast() }
+    }
+
+    /// Returns the order of the free block at `offset`, if any.
+    fn free_order(&self, offset: usize) -> Option<usize> {
+        match unsafe { *self.orders.as_ptr().add(offset >> MIN_ORDER) } {
+            0 => None,
+            order => Some(order as usize - 1),
+        }
+    }
+
+    fn set_free_order(&mut self, offset: usize, order: Option<usize>) {
+        let value = order.map_or(0, |order| order as u8 + 1);
+        unsafe { *self.orders.as_ptr().add(offset >> MIN_ORDER) = value };
+    }
+
+    /// Adds the block at `offset` to the free list for `order`.
+    fn push(&mut self, offset: usize, order: usize) {
+        let node = self.node(offset);
+        unsafe {
+            node.as_ptr().write(FreeNode { prev: None, next: self.heads[order] });
+            if let Some(mut next) = self.heads[order] {
+                next.as_mut().prev = Some(node);
+            }
+        }
+        self.heads[order] = Some(node);
+        self.set_free_order(offset, Some(order));
+        self.free_bytes += 1 << order;
+    }
+
+    /// Removes the block at `offset` from the free list for `order`.
+    fn remove(&mut self, offset: usize, order: usize) {
+        let node = self.node(offset);
+        unsafe {
+            let FreeNode { prev, next } = node.as_ptr().read();
+            match prev {
+                Some(mut prev) => prev.as_mut().next = next,
+                None => self.heads[order] = next,
+            }
+            if let Some(mut next) = next {
+                next.as_mut().prev = prev;
+            }
+        }
+        self.set_free_order(offset, None);
+        self.free_bytes -= 1 << order;
+    }
+
+    /// Returns the offset of the buddy of the block at `offset`, if it is
+    /// free and has the given order.
+    fn free_buddy(&self, offset: usize, order: usize) -> Option<usize> {
+        let buddy = offset ^ (1 << order);
+        if order < MAX_ORDER
+            && buddy + (1 << order) <= self.layout.size()
+            && self.free_order(buddy) == Some(order)
+        {
+            Some(buddy)
+        } else {
+            None
+        }
+    }
+
+    /// Frees the block at `offset`, merging it with its free buddies.
+    fn free(&mut self, mut offset: usize, mut order: usize) {
+        while let Some(buddy) = self.free_buddy(offset, order) {
+            self.remove(buddy, order);
+            offset = cmp::min(offset, buddy);
+            order += 1;
+        }
+        self.push(offset, order);
+    }
+}
+
+impl<const MIN_ORDER: usize, const MAX_ORDER: usize> fmt::Debug for Buddy<MIN_ORDER, MAX_ORDER> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Buddy")
+            .field("region", &self.region)
+            .field("len", &self.layout.size())
+            .field("free_bytes", &self.free_bytes)
+            .finish()
+    }
+}
+
+unsafe impl<const MIN_ORDER: usize, const MAX_ORDER: usize> AllocRef for Buddy<MIN_ORDER, MAX_ORDER> {
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let order = self.order_for(layout).ok_or(AllocErr)?;
+        let mut block_order = (order..=MAX_ORDER)
+            .find(|&order| self.heads[order].is_some())
+            .ok_or(AllocErr)?;
+        let node = self.heads[block_order].unwrap();
+        let offset = self.offset_of(node.cast());
+        self.remove(offset, block_order);
+        while block_order > order {
+            block_order -= 1;
+            self.push(offset + (1 << block_order), block_order);
+        }
+        let ptr = unsafe { NonNull::new_unchecked(self.region.as_ptr().add(offset)) };
+        Ok((ptr, 1 << order))
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let order = self.order_for(layout).unwrap();
+        self.free(self.offset_of(ptr), order)
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        match self.order_for(*layout) {
+            Some(order) => (layout.size(), 1 << order),
+            None => (layout.size(), layout.size()),
+        }
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let order = self.order_for(layout).unwrap();
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_order = self.order_for(new_layout).ok_or(CannotReallocInPlace)?;
+        let offset = self.offset_of(ptr);
+        // The block can only grow if it is the lower half of each of the
+        // blocks it is merged into, and the upper halves are free.
+        if (order..new_order).any(|o| offset & (1 << o) != 0 || self.free_buddy(offset, o).is_none()) {
+            return Err(CannotReallocInPlace);
+        }
+        for o in order..new_order {
+            self.remove(offset + (1 << o), o);
+        }
+        Ok(1 << new_order)
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let order = self.order_for(layout).unwrap();
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_order = self.order_for(new_layout).unwrap();
+        let offset = self.offset_of(ptr);
+        for o in (new_order..order).rev() {
+            self.push(offset + (1 << o), o);
+        }
+        Ok(1 << new_order)
+    }
+}
+
+impl<const MIN_ORDER: usize, const MAX_ORDER: usize> Owns for Buddy<MIN_ORDER, MAX_ORDER> {
+    fn owns(&self, ptr: NonNull<u8>, _layout: Layout) -> bool {
+        let start = self.region.as_ptr() as usize;
+        (start..start + self.layout.size()).contains(&(ptr.as_ptr() as usize))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests;
@@ -0,0 +1,73 @@
+use super::*;
+use crate::alloc::Global;
+
+type TestBuddy = Buddy<4, 12>;
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[test]
+fn buddy_splits_and_merges() {
+    let mut buddy = TestBuddy::from_alloc(&mut Global, 1 << 14).unwrap();
+    // The bookkeeping takes 1024 bytes out of the first 4096-byte block.
+    let free = buddy.free_bytes();
+    assert_eq!(free, (1 << 14) - 1024);
+    assert_eq!(buddy.largest_free_block(), Some(4096));
+
+    let (a, size) = buddy.alloc(layout(100)).unwrap();
+    assert_eq!(size, 128);
+    assert_eq!(buddy.usable_size(&layout(100)), (100, 128));
+    let (b, size) = buddy.alloc(layout(100)).unwrap();
+    assert_eq!(size, 128);
+    // The two blocks are buddies.
+    assert_eq!(a.as_ptr() as usize ^ b.as_ptr() as usize, 128);
+    assert!(buddy.owns(a, layout(100)));
+    assert!(!buddy.owns(NonNull::from(&free).cast(), layout(8)));
+
+    unsafe {
+        buddy.dealloc(a, layout(128));
+        buddy.dealloc(b, layout(100));
+    }
+    assert_eq!(buddy.free_bytes(), free);
+    assert_eq!(buddy.largest_free_block(), Some(4096));
+
+    let (c, size) = buddy.alloc(layout(4096)).unwrap();
+    assert_eq!(size, 4096);
+    assert_eq!(buddy.alloc(layout(4097)), Err(AllocErr));
+    assert_eq!(buddy.alloc(Layout::from_size_align(16, 8192).unwrap()), Err(AllocErr));
+    unsafe {
+        buddy.dealloc(c, layout(4096));
+        buddy.free_region(&mut Global);
+    }
+}
+
+#[test]
+fn buddy_resizes_in_place() {
+    let mut buddy = TestBuddy::from_alloc(&mut Global, 1 << 14).unwrap();
+    let free = buddy.free_bytes();
+    unsafe {
+        let (a, _) = buddy.alloc(layout(16)).unwrap();
+        assert_eq!(buddy.grow_in_place(a, layout(16), 1000), Ok(1024));
+        let (b, _) = buddy.alloc(layout(1024)).unwrap();
+        // `a` is the upper half of a block whose lower half holds the
+        // bookkeeping, so it can't grow anymore.
+        assert_eq!(buddy.grow_in_place(a, layout(1000), 2000), Err(CannotReallocInPlace));
+        assert_eq!(buddy.shrink_in_place(a, layout(1000), 40), Ok(64));
+        assert_eq!(buddy.free_bytes(), free - 64 - 1024);
+
+        // Moving `a` copies its contents.
+        a.as_ptr().write_bytes(0x42, 40);
+        let (c, size) = buddy.realloc(a, layout(40), 2000).unwrap();
+        assert_eq!(size, 2048);
+        assert_ne!(c, a);
+        assert_eq!(*c.as_ptr().add(39), 0x42);
+
+        // `b` is the lower half of its parent, and its buddy is free.
+        assert_eq!(buddy.grow_in_place(b, layout(1024), 2048), Ok(2048));
+        buddy.dealloc(b, layout(2048));
+        buddy.dealloc(c, layout(2048));
+        assert_eq!(buddy.free_bytes(), free);
+        buddy.free_region(&mut Global);
+    }
+}
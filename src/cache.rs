@@ -0,0 +1,420 @@
+//! Thread-local caching in front of a shared allocator.
+//!
+//! [`ThreadCache`] keeps, for each thread using it, free lists of small
+//! blocks, so that most allocations and deallocations don't need to lock
+//! the shared backing allocator.
+//!
+//! [`ThreadCache`]: struct.ThreadCache.html
+
+use core::cell::RefCell;
+use core::cmp;
+use core::fmt;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use std::boxed::Box;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::vec::Vec;
+
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout, SharedAllocRef};
+
+/// Size of the smallest size class. This is also the alignment of all the
+/// cached blocks.
+const MIN_SIZE: usize = 16;
+
+/// Number of size classes, from `MIN_SIZE` to 32KiB.
+const CLASSES: usize = 12;
+
+/// Largest size of cached blocks.
+const MAX_SIZE: usize = MIN_SIZE << (CLASSES - 1);
+
+/// Returns the size class for the given layout, or `None` if blocks for
+/// that layout are not cached.
+fn class_of(layout: Layout) -> Option<usize> {
+    if layout.size() > MAX_SIZE || layout.align() > MIN_SIZE {
+        return None;
+    }
+    let size = cmp::max(layout.size(), MIN_SIZE).next_power_of_two();
+    Some((size / MIN_SIZE).trailing_zeros() as usize)
+}
+
+/// Returns the layout of the blocks of the given size class.
+fn class_layout(class: usize) -> Layout {
+    unsafe { Layout::from_size_align_unchecked(MIN_SIZE << class, MIN_SIZE) }
+}
+
+struct FreeBlock {
+    next: Option<NonNull<FreeBlock>>,
+}
+
+/// Intrusive free lists, one per size class.
+struct Lists {
+    heads: [Option<NonNull<FreeBlock>>; CLASSES],
+    counts: [usize; CLASSES],
+}
+
+impl Lists {
+    fn pop(&mut self, class: usize) -> Option<NonNull<u8>> {
+        let block = self.heads[class]?;
+        self.heads[class] = unsafe { block.as_ref().next };
+        self.counts[class] -= 1;
+        Some(block.cast())
+    }
+
+    unsafe fn push(&mut self, class: usize, ptr: NonNull<u8>) {
+        let block = ptr.cast::<FreeBlock>();
+        block.as_ptr().write(FreeBlock { next: self.heads[class] });
+        self.heads[class] = Some(block);
+        self.counts[class] += 1;
+    }
+
+    /// Returns up to `count` blocks of the given size class to `a`.
+    fn flush<A: AllocRef>(&mut self, a: &mut A, class: usize, count: usize) {
+        for _ in 0..count {
+            match self.pop(class) {
+                Some(ptr) => unsafe { a.dealloc(ptr, class_layout(class)) },
+                None => break,
+            }
+        }
+    }
+
+    /// Returns all the blocks to `a`.
+    fn flush_all<A: AllocRef>(&mut self, a: &mut A) {
+        for class in 0..CLASSES {
+            self.flush(a, class, usize::MAX);
+        }
+    }
+}
+
+/// A function returning all the blocks in the given lists to the `Inner`
+/// pointed to by its first argument.
+type Release = unsafe fn(*const (), &mut Lists);
+
+/// The state of a thread's cache, shared between the thread and the
+/// `ThreadCache`, which drains it when dropped.
+struct LocalState {
+    lists: Lists,
+    /// The `Inner` owning this cache and the function returning blocks to
+    /// it, or `None` once the `ThreadCache` is dropped.
+    owner: Option<(*const (), Release)>,
+}
+
+// The blocks in the lists are not tied to any thread, and the owner is only
+// used while the lock is held, which the `ThreadCache` also takes before
+// going away.
+unsafe impl Send for LocalState {}
+
+struct Local {
+    state: Mutex<LocalState>,
+}
+
+impl Local {
+    fn state(&self) -> MutexGuard<'_, LocalState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns all the blocks to the owner, if it still exists.
+    fn release(&self) {
+        let mut state = self.state();
+        let state = &mut *state;
+        if let Some((inner, release)) = state.owner {
+            unsafe { release(inner, &mut state.lists) };
+        }
+    }
+}
+
+/// The caches of the current thread, keyed by `ThreadCache` id. They are
+/// released when the thread exits.
+struct Locals(Vec<(usize, Arc<Local>)>);
+
+impl Drop for Locals {
+    fn drop(&mut self) {
+        for (_, local) in &self.0 {
+            local.release();
+        }
+    }
+}
+
+std::thread_local!(static LOCALS: RefCell<Locals> = const { RefCell::new(Locals(Vec::new())) });
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+struct Inner<A> {
+    a: Mutex<A>,
+    id: usize,
+    capacity: usize,
+    locals: Mutex<Vec<Arc<Local>>>,
+}
+
+impl<A: AllocRef> Inner<A> {
+    fn a(&self) -> MutexGuard<'_, A> {
+        self.a.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    unsafe fn release(inner: *const (), lists: &mut Lists) {
+        let inner = &*(inner as *const Inner<A>);
+        lists.flush_all(&mut *inner.a());
+    }
+}
+
+/// A caching front-end for a shared allocator.
+///
+/// Each thread allocating through a `ThreadCache` gets its own free lists
+/// for power-of-two size classes from 16 bytes to 32KiB. Blocks in these
+/// size classes are taken from, and returned to, the free lists of the
+/// calling thread. The backing allocator, protected by a mutex, is only
+/// involved when a free list is empty, in which case half its capacity is
+/// allocated in one go, or when it exceeds its capacity, in which case half
+/// of it is flushed back in one go. Larger blocks, and blocks with an
+/// alignment larger than 16 bytes, are directly allocated from the backing
+/// allocator.
+///
+/// Blocks may be deallocated on a different thread than the one that
+/// allocated them, in which case they end up in the free lists of the
+/// deallocating thread. When a thread exits, the blocks in its free lists
+/// are returned to the backing allocator. When the `ThreadCache` is
+/// dropped, the blocks in the free lists of all threads are.
+///
+/// `ThreadCache` implements `SharedAllocRef`, so `&ThreadCache` implements
+/// `AllocRef`. It relies on thread-local storage and on allocating through
+/// the global allocator, so it can't be used as the global allocator.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::cache::ThreadCache;
+/// use allocator_api::stats::{Counting, Stats};
+/// use allocator_api::{Box, Global};
+///
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// static STATS: Stats = Stats::new();
+///
+/// let cache = Arc::new(ThreadCache::new(Counting::new(Global, &STATS)));
+/// let threads: Vec<_> = (0..4)
+///     .map(|_| {
+///         let cache = cache.clone();
+///         thread::spawn(move || {
+///             for i in 0..1000 {
+///                 drop(Box::new_in(i, &*cache));
+///             }
+///         })
+///     })
+///     .collect();
+/// for t in threads {
+///     t.join().unwrap();
+/// }
+/// // Each thread only allocated one batch of blocks, and released it when
+/// // exiting.
+/// let snapshot = STATS.snapshot();
+/// assert!(snapshot.allocs <= 4 * 32);
+/// assert_eq!(snapshot.live_bytes, 0);
+/// # }
+/// ```
+pub struct ThreadCache<A: AllocRef> {
+    // Boxed so that thread caches can point to it.
+    inner: Box<Inner<A>>,
+}
+
+impl<A: AllocRef + Send> ThreadCache<A> {
+    /// Creates a cache in front of `a`, keeping up to 64 blocks per size
+    /// class and per thread.
+    pub fn new(a: A) -> Self {
+        ThreadCache::with_capacity(a, 64)
+    }
+
+    /// Creates a cache in front of `a`, keeping up to `capacity` blocks per
+    /// size class and per thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is less than 2.
+    pub fn with_capacity(a: A, capacity: usize) -> Self {
+        assert!(capacity >= 2, "ThreadCache capacity must be at least 2");
+        ThreadCache {
+            inner: Box::new(Inner {
+                a: Mutex::new(a),
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                capacity,
+                locals: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Returns the blocks cached by the current thread to the backing
+    /// allocator.
+    pub fn flush(&self) {
+        self.with_lists(|lists| lists.flush_all(&mut *self.inner.a()));
+    }
+
+    /// Returns the number of blocks cached by the current thread.
+    pub fn cached_blocks(&self) -> usize {
+        self.with_lists(|lists| lists.counts.iter().sum()).unwrap_or(0)
+    }
+
+    /// Runs `f` with the free lists of the current thread. Returns `None`
+    /// if they are not available because the thread is exiting.
+    fn with_lists<R>(&self, f: impl FnOnce(&mut Lists) -> R) -> Option<R> {
+        let inner = &*self.inner;
+        let local = LOCALS
+            .try_with(|locals| {
+                let mut locals = locals.borrow_mut();
+                if let Some((_, local)) = locals.0.iter().find(|(id, _)| *id == inner.id) {
+                    return local.clone();
+                }
+                // Forget about the caches of `ThreadCache`s that were dropped.
+                locals.0.retain(|(_, local)| Arc::strong_count(local) > 1);
+                let local = Arc::new(Local {
+                    state: Mutex::new(LocalState {
+                        lists: Lists { heads: [None; CLASSES], counts: [0; CLASSES] },
+                        owner: Some((inner as *const Inner<A> as *const (), Inner::<A>::release)),
+                    }),
+                });
+                locals.0.push((inner.id, local.clone()));
+                let mut all = inner.locals.lock().unwrap_or_else(|e| e.into_inner());
+                // Forget about the caches of threads that exited.
+                all.retain(|local| Arc::strong_count(local) > 1);
+                all.push(local.clone());
+                local
+            })
+            .ok()?;
+        let mut state = local.state();
+        Some(f(&mut state.lists))
+    }
+}
+
+impl<A: AllocRef> Drop for ThreadCache<A> {
+    fn drop(&mut self) {
+        let mut locals = self.inner.locals.lock().unwrap_or_else(|e| e.into_inner());
+        for local in locals.drain(..) {
+            let mut state = local.state();
+            state.lists.flush_all(&mut *self.inner.a());
+            state.owner = None;
+        }
+    }
+}
+
+impl<A: AllocRef> fmt::Debug for ThreadCache<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThreadCache").field("capacity", &self.inner.capacity).finish()
+    }
+}
+
+unsafe impl<A: AllocRef + Send> SharedAllocRef for ThreadCache<A> {
+    fn alloc(&self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let class = match class_of(layout) {
+            Some(class) => class,
+            None => return self.inner.a().alloc(layout),
+        };
+        let block_layout = class_layout(class);
+        let ptr = self.with_lists(|lists| {
+            if let Some(ptr) = lists.pop(class) {
+                return Ok(ptr);
+            }
+            let mut a = self.inner.a();
+            let (ptr, _) = a.alloc(block_layout)?;
+            for _ in 1..self.inner.capacity / 2 {
+                match a.alloc(block_layout) {
+                    Ok((ptr, _)) => unsafe { lists.push(class, ptr) },
+                    Err(_) => break,
+                }
+            }
+            Ok(ptr)
+        });
+        let ptr = match ptr {
+            Some(ptr) => ptr?,
+            None => self.inner.a().alloc(block_layout)?.0,
+        };
+        Ok((ptr, block_layout.size()))
+    }
+
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        let class = match class_of(layout) {
+            Some(class) => class,
+            None => return self.inner.a().dealloc(ptr, layout),
+        };
+        let cached = self.with_lists(|lists| {
+            lists.push(class, ptr);
+            if lists.counts[class] > self.inner.capacity {
+                lists.flush(&mut *self.inner.a(), class, self.inner.capacity / 2);
+            }
+        });
+        if cached.is_none() {
+            self.inner.a().dealloc(ptr, class_layout(class));
+        }
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        match class_of(*layout) {
+            Some(class) => (layout.size(), class_layout(class).size()),
+            None => self.inner.a().usable_size(layout),
+        }
+    }
+
+    fn alloc_zeroed(&self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        if class_of(layout).is_none() {
+            return self.inner.a().alloc_zeroed(layout);
+        }
+        let (ptr, size) = self.alloc(layout)?;
+        unsafe { ptr::write_bytes(ptr.as_ptr(), 0, size) };
+        Ok((ptr, size))
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        match (class_of(layout), class_of(new_layout)) {
+            (None, None) => self.inner.a().realloc(ptr, layout, new_size),
+            (Some(class), Some(new_class)) if class == new_class => {
+                Ok((ptr, class_layout(class).size()))
+            }
+            _ => {
+                let result = self.alloc(new_layout);
+                if let Ok((new_ptr, _)) = result {
+                    let size = cmp::min(layout.size(), new_size);
+                    ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), size);
+                    self.dealloc(ptr, layout);
+                }
+                result
+            }
+        }
+    }
+
+    unsafe fn grow_in_place(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        match (class_of(layout), class_of(new_layout)) {
+            (None, None) => self.inner.a().grow_in_place(ptr, layout, new_size),
+            (Some(class), Some(new_class)) if class == new_class => Ok(class_layout(class).size()),
+            _ => Err(CannotReallocInPlace),
+        }
+    }
+
+    unsafe fn shrink_in_place(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        match (class_of(layout), class_of(new_layout)) {
+            (None, None) => self.inner.a().shrink_in_place(ptr, layout, new_size),
+            (Some(class), Some(new_class)) if class == new_class => Ok(class_layout(class).size()),
+            _ => Err(CannotReallocInPlace),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
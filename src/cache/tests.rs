@@ -0,0 +1,46 @@
+use super::*;
+use crate::alloc::Global;
+use crate::debug::LeakCheck;
+use crate::raw_vec::RawVec;
+use std::thread;
+
+#[test]
+fn thread_cache_size_classes() {
+    assert_eq!(class_of(Layout::from_size_align(0, 1).unwrap()), Some(0));
+    assert_eq!(class_of(Layout::from_size_align(16, 16).unwrap()), Some(0));
+    assert_eq!(class_of(Layout::from_size_align(17, 8).unwrap()), Some(1));
+    assert_eq!(class_of(Layout::from_size_align(MAX_SIZE, 1).unwrap()), Some(CLASSES - 1));
+    assert_eq!(class_of(Layout::from_size_align(MAX_SIZE + 1, 1).unwrap()), None);
+    assert_eq!(class_of(Layout::from_size_align(16, 32).unwrap()), None);
+}
+
+#[test]
+fn thread_cache_returns_blocks() {
+    let leaks = LeakCheck::new(Global);
+    let cache = ThreadCache::with_capacity(leaks.clone(), 8);
+    let mut v: RawVec<u8, _> = RawVec::with_capacity_in(20, &cache);
+    assert_eq!(v.capacity(), 32);
+    assert_eq!(leaks.live_allocations(), 4);
+    v.reserve(32, 100_000);
+    assert_eq!(leaks.live_allocations(), 5);
+    v.shrink_to_fit(16);
+    // 3 blocks of 16 bytes left from the refill, and 4 of 32 bytes.
+    assert_eq!(cache.cached_blocks(), 7);
+    drop(v);
+
+    // Blocks freed on another thread are cached there, and released when
+    // that thread exits.
+    let boxes: Vec<_> = (0..20).map(|i| crate::boxed::Box::new_in(i, &cache)).collect();
+    thread::scope(|s| {
+        // Joining the thread waits for its thread-local destructors.
+        s.spawn(move || drop(boxes)).join().unwrap();
+    });
+    assert_eq!(leaks.live_allocations(), cache.cached_blocks());
+
+    cache.flush();
+    assert_eq!(leaks.live_allocations(), 0);
+    let b = crate::boxed::Box::new_in(42u64, &cache);
+    drop(b);
+    drop(cache);
+    leaks.assert_no_leaks();
+}
@@ -0,0 +1,212 @@
+use core::cmp;
+use core::ptr::{self, NonNull};
+
+use super::Owns;
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout};
+
+/// An allocator trying a primary allocator first, and falling back to a
+/// secondary one when the primary fails.
+///
+/// The primary allocator must implement [`Owns`], so that deallocations and
+/// reallocations are routed to the allocator that owns the block.
+/// Reallocations of blocks owned by the primary allocator that it can't
+/// satisfy move the block to the secondary allocator. Blocks never move
+/// from the secondary allocator to the primary one.
+///
+/// [`Owns`]: trait.Owns.html
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::alloc::{AllocErr, AllocRef, Layout};
+/// use allocator_api::combinators::{Fallback, Owns};
+/// use allocator_api::{Global, RawVec};
+/// use std::ptr::NonNull;
+///
+/// // A bump allocator over a fixed buffer, that never frees.
+/// struct Arena<'a> {
+///     buf: &'a mut [u8],
+///     used: usize,
+/// }
+///
+/// unsafe impl AllocRef for Arena<'_> {
+///     fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+///         let start = self.buf.as_ptr() as usize;
+///         let offset = ((start + self.used + layout.align() - 1) & !(layout.align() - 1)) - start;
+///         if offset + layout.size() > self.buf.len() {
+///             return Err(AllocErr);
+///         }
+///         self.used = offset + layout.size();
+///         Ok((NonNull::from(&mut self.buf[offset]), layout.size()))
+///     }
+///
+///     unsafe fn dealloc(&mut self, _ptr: NonNull<u8>, _layout: Layout) {}
+/// }
+///
+/// impl Owns for Arena<'_> {
+///     fn owns(&self, ptr: NonNull<u8>, _layout: Layout) -> bool {
+///         self.buf.as_ptr_range().contains(&(ptr.as_ptr() as *const u8))
+///     }
+/// }
+///
+/// let mut buf = [0; 64];
+/// let mut a = Fallback::new(Arena { buf: &mut buf, used: 0 }, Global);
+/// let mut v: RawVec<u8, _> = RawVec::with_capacity_in(32, &mut a);
+/// assert!(v.alloc().primary().owns(NonNull::new(v.ptr()).unwrap(), Layout::new::<u8>()));
+/// v.reserve(32, 64);
+/// assert!(!v.alloc().primary().owns(NonNull::new(v.ptr()).unwrap(), Layout::new::<u8>()));
+/// # }
+/// ```
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Fallback<P, S> {
+    primary: P,
+    secondary: S,
+}
+
+impl<P: AllocRef + Owns, S: AllocRef> Fallback<P, S> {
+    /// Creates an allocator trying `primary` first, then `secondary`.
+    pub fn new(primary: P, secondary: S) -> Self {
+        Fallback { primary, secondary }
+    }
+
+    /// Returns a reference to the primary allocator.
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    /// Returns a reference to the secondary allocator.
+    pub fn secondary(&self) -> &S {
+        &self.secondary
+    }
+
+    /// Consumes the allocator, returning the primary and secondary
+    /// allocators.
+    pub fn into_parts(self) -> (P, S) {
+        (self.primary, self.secondary)
+    }
+
+    /// Moves a block owned by the primary allocator to the secondary one.
+    unsafe fn move_to_secondary(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+        zeroed: bool,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let (new_ptr, size) = if zeroed {
+            self.secondary.alloc_zeroed(new_layout)?
+        } else {
+            self.secondary.alloc(new_layout)?
+        };
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), cmp::min(layout.size(), new_size));
+        self.primary.dealloc(ptr, layout);
+        Ok((new_ptr, size))
+    }
+}
+
+unsafe impl<P: AllocRef + Owns, S: AllocRef> AllocRef for Fallback<P, S> {
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.primary.alloc(layout).or_else(|_| self.secondary.alloc(layout))
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        if self.primary.owns(ptr, layout) {
+            self.primary.dealloc(ptr, layout)
+        } else {
+            self.secondary.dealloc(ptr, layout)
+        }
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        // The block may come from either allocator.
+        let (primary_min, primary_max) = self.primary.usable_size(layout);
+        let (secondary_min, secondary_max) = self.secondary.usable_size(layout);
+        (cmp::max(primary_min, secondary_min), cmp::min(primary_max, secondary_max))
+    }
+
+    fn alloc_zeroed(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.primary
+            .alloc_zeroed(layout)
+            .or_else(|_| self.secondary.alloc_zeroed(layout))
+    }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        if self.primary.owns(ptr, layout) {
+            match self.primary.realloc(ptr, layout, new_size) {
+                Ok(result) => Ok(result),
+                Err(_) => self.move_to_secondary(ptr, layout, new_size, false),
+            }
+        } else {
+            self.secondary.realloc(ptr, layout, new_size)
+        }
+    }
+
+    unsafe fn realloc_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        if self.primary.owns(ptr, layout) {
+            match self.primary.realloc_zeroed(ptr, layout, new_size) {
+                Ok(result) => Ok(result),
+                Err(_) => self.move_to_secondary(ptr, layout, new_size, true),
+            }
+        } else {
+            self.secondary.realloc_zeroed(ptr, layout, new_size)
+        }
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        if self.primary.owns(ptr, layout) {
+            self.primary.grow_in_place(ptr, layout, new_size)
+        } else {
+            self.secondary.grow_in_place(ptr, layout, new_size)
+        }
+    }
+
+    unsafe fn grow_in_place_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        if self.primary.owns(ptr, layout) {
+            self.primary.grow_in_place_zeroed(ptr, layout, new_size)
+        } else {
+            self.secondary.grow_in_place_zeroed(ptr, layout, new_size)
+        }
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        if self.primary.owns(ptr, layout) {
+            self.primary.shrink_in_place(ptr, layout, new_size)
+        } else {
+            self.secondary.shrink_in_place(ptr, layout, new_size)
+        }
+    }
+}
+
+impl<P: Owns, S: Owns> Owns for Fallback<P, S> {
+    fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        self.primary.owns(ptr, layout) || self.secondary.owns(ptr, layout)
+    }
+}
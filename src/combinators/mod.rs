@@ -0,0 +1,47 @@
+//! Allocators built by composing other allocators.
+//!
+//! Composing allocators requires knowing which of them a given block comes
+//! from, so that it can be deallocated or reallocated by the right one. The
+//! [`Owns`] trait answers that question.
+//!
+//! [`Owns`]: trait.Owns.html
+
+use core::ptr::NonNull;
+
+use crate::alloc::Layout;
+
+mod fallback;
+mod segregate;
+
+pub use self::fallback::Fallback;
+pub use self::segregate::{Segregate, SegregateBuilder};
+
+/// Allocators able to tell whether they allocated a given block.
+///
+/// Allocator wrappers implement `Owns` when the allocator they wrap does.
+pub trait Owns {
+    /// Returns whether the block at `ptr`, allocated with `layout` by any
+    /// allocator, was allocated by this allocator and is still live.
+    ///
+    /// Implementations may only be able to tell whether `ptr` belongs to
+    /// memory managed by this allocator, in which case they may return
+    /// `true` for blocks that have already been deallocated.
+    fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool;
+}
+
+impl<A: Owns + ?Sized> Owns for &A {
+    #[inline]
+    fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        (**self).owns(ptr, layout)
+    }
+}
+
+impl<A: Owns + ?Sized> Owns for &mut A {
+    #[inline]
+    fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        (**self).owns(ptr, layout)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests;
@@ -0,0 +1,281 @@
+use core::cmp;
+use core::ptr::{self, NonNull};
+
+use super::Owns;
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout};
+
+/// An allocator routing requests of up to `THRESHOLD` bytes to a `Small`
+/// allocator, and larger requests to a `Large` allocator.
+///
+/// Blocks are routed by the size of the layout they are used with, so
+/// reallocations crossing the threshold move blocks from one allocator to
+/// the other, and in-place reallocations crossing the threshold fail. For
+/// blocks from the `Small` allocator, the returned size is capped to
+/// `THRESHOLD`, so that they can't be used with layouts routing them to the
+/// `Large` allocator.
+///
+/// Several thresholds can be combined with a [`SegregateBuilder`].
+///
+/// [`SegregateBuilder`]: struct.SegregateBuilder.html
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::combinators::Segregate;
+/// use allocator_api::stats::{Counting, Stats};
+/// use allocator_api::{Global, RawVec};
+///
+/// let (small, large) = (Stats::new(), Stats::new());
+/// let a: Segregate<_, _, 64> = Segregate::new(Counting::new(Global, &small), Counting::new(Global, &large));
+/// let mut v: RawVec<u8, _> = RawVec::with_capacity_in(16, a);
+/// assert_eq!(small.snapshot().live_bytes, 16);
+/// v.reserve(16, 48);
+/// assert_eq!(small.snapshot().live_bytes, 64);
+/// v.reserve(64, 1);
+/// assert_eq!(small.snapshot().live_bytes, 0);
+/// assert_eq!(large.snapshot().live_bytes, 128);
+/// # }
+/// ```
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Segregate<Small, Large, const THRESHOLD: usize> {
+    small: Small,
+    large: Large,
+}
+
+impl<Small: AllocRef, Large: AllocRef, const THRESHOLD: usize> Segregate<Small, Large, THRESHOLD> {
+    /// Creates an allocator routing requests of up to `THRESHOLD` bytes to
+    /// `small`, and larger requests to `large`.
+    pub fn new(small: Small, large: Large) -> Self {
+        Segregate { small, large }
+    }
+
+    /// Returns a reference to the allocator for small requests.
+    pub fn small(&self) -> &Small {
+        &self.small
+    }
+
+    /// Returns a reference to the allocator for large requests.
+    pub fn large(&self) -> &Large {
+        &self.large
+    }
+
+    /// Consumes the allocator, returning the allocators for small and large
+    /// requests.
+    pub fn into_parts(self) -> (Small, Large) {
+        (self.small, self.large)
+    }
+
+    fn cap(result: Result<(NonNull<u8>, usize), AllocErr>) -> Result<(NonNull<u8>, usize), AllocErr> {
+        result.map(|(ptr, size)| (ptr, cmp::min(size, THRESHOLD)))
+    }
+
+    /// Moves a block from one allocator to the other.
+    unsafe fn migrate(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+        zeroed: bool,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let (new_ptr, size) = if zeroed {
+            self.alloc_zeroed(new_layout)?
+        } else {
+            self.alloc(new_layout)?
+        };
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), cmp::min(layout.size(), new_size));
+        self.dealloc(ptr, layout);
+        Ok((new_ptr, size))
+    }
+}
+
+unsafe impl<Small, Large, const THRESHOLD: usize> AllocRef for Segregate<Small, Large, THRESHOLD>
+where
+    Small: AllocRef,
+    Large: AllocRef,
+{
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        if layout.size() <= THRESHOLD {
+            Self::cap(self.small.alloc(layout))
+        } else {
+            self.large.alloc(layout)
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() <= THRESHOLD {
+            self.small.dealloc(ptr, layout)
+        } else {
+            self.large.dealloc(ptr, layout)
+        }
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        if layout.size() <= THRESHOLD {
+            let (min, max) = self.small.usable_size(layout);
+            (min, cmp::min(max, THRESHOLD))
+        } else {
+            self.large.usable_size(layout)
+        }
+    }
+
+    fn alloc_zeroed(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        if layout.size() <= THRESHOLD {
+            Self::cap(self.small.alloc_zeroed(layout))
+        } else {
+            self.large.alloc_zeroed(layout)
+        }
+    }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        match (layout.size() <= THRESHOLD, new_size <= THRESHOLD) {
+            (true, true) => Self::cap(self.small.realloc(ptr, layout, new_size)),
+            (false, false) => self.large.realloc(ptr, layout, new_size),
+            _ => self.migrate(ptr, layout, new_size, false),
+        }
+    }
+
+    unsafe fn realloc_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        match (layout.size() <= THRESHOLD, new_size <= THRESHOLD) {
+            (true, true) => Self::cap(self.small.realloc_zeroed(ptr, layout, new_size)),
+            (false, false) => self.large.realloc_zeroed(ptr, layout, new_size),
+            _ => self.migrate(ptr, layout, new_size, true),
+        }
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        match (layout.size() <= THRESHOLD, new_size <= THRESHOLD) {
+            (true, true) => {
+                let size = self.small.grow_in_place(ptr, layout, new_size)?;
+                Ok(cmp::min(size, THRESHOLD))
+            }
+            (false, false) => self.large.grow_in_place(ptr, layout, new_size),
+            _ => Err(CannotReallocInPlace),
+        }
+    }
+
+    unsafe fn grow_in_place_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        match (layout.size() <= THRESHOLD, new_size <= THRESHOLD) {
+            (true, true) => {
+                let size = self.small.grow_in_place_zeroed(ptr, layout, new_size)?;
+                Ok(cmp::min(size, THRESHOLD))
+            }
+            (false, false) => self.large.grow_in_place_zeroed(ptr, layout, new_size),
+            _ => Err(CannotReallocInPlace),
+        }
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        match (layout.size() <= THRESHOLD, new_size <= THRESHOLD) {
+            (true, true) => {
+                let size = self.small.shrink_in_place(ptr, layout, new_size)?;
+                Ok(cmp::min(size, THRESHOLD))
+            }
+            (false, false) => self.large.shrink_in_place(ptr, layout, new_size),
+            _ => Err(CannotReallocInPlace),
+        }
+    }
+}
+
+impl<Small: Owns, Large: Owns, const THRESHOLD: usize> Owns for Segregate<Small, Large, THRESHOLD> {
+    fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        if layout.size() <= THRESHOLD {
+            self.small.owns(ptr, layout)
+        } else {
+            self.large.owns(ptr, layout)
+        }
+    }
+}
+
+/// A builder for a size-class table made of nested [`Segregate`]
+/// allocators.
+///
+/// Thresholds must be given in increasing order.
+///
+/// [`Segregate`]: struct.Segregate.html
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::combinators::SegregateBuilder;
+/// use allocator_api::stats::{Counting, Stats};
+/// use allocator_api::{Box, Global};
+///
+/// let stats = [Stats::new(), Stats::new(), Stats::new()];
+/// let a = SegregateBuilder::new(Counting::new(Global, &stats[0]))
+///     .above::<16, _>(Counting::new(Global, &stats[1]))
+///     .above::<4096, _>(Counting::new(Global, &stats[2]))
+///     .build();
+///
+/// let b = Box::new_in([0u8; 100], a);
+/// assert_eq!(stats[1].snapshot().live_bytes, 100);
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct SegregateBuilder<A> {
+    a: A,
+    threshold: Option<usize>,
+}
+
+impl<A: AllocRef> SegregateBuilder<A> {
+    /// Creates a builder, with `smallest` handling the requests below the
+    /// first threshold.
+    pub fn new(smallest: A) -> Self {
+        SegregateBuilder { a: smallest, threshold: None }
+    }
+
+    /// Adds an allocator handling the requests of more than `THRESHOLD`
+    /// bytes, and up to the next threshold, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `THRESHOLD` is not larger than the previous threshold.
+    pub fn above<const THRESHOLD: usize, L: AllocRef>(
+        self,
+        large: L,
+    ) -> SegregateBuilder<Segregate<A, L, THRESHOLD>> {
+        if let Some(threshold) = self.threshold {
+            assert!(
+                THRESHOLD > threshold,
+                "threshold {} is not larger than the previous threshold {}",
+                THRESHOLD,
+                threshold
+            );
+        }
+        SegregateBuilder { a: Segregate::new(self.a, large), threshold: Some(THRESHOLD) }
+    }
+
+    /// Returns the resulting allocator.
+    pub fn build(self) -> A {
+        self.a
+    }
+}
@@ -0,0 +1,66 @@
+use super::*;
+use crate::alloc::{AllocRef, Global};
+use crate::debug::LeakCheck;
+use crate::limit::{Budget, Quota};
+use crate::raw_vec::RawVec;
+
+#[test]
+fn fallback_routes_to_owner() {
+    let budget = Budget::new(32);
+    let primary = LeakCheck::new(Global);
+    let secondary = LeakCheck::new(Global);
+    let mut a = Fallback::new(Quota::new(primary.clone(), &budget), secondary.clone());
+
+    let mut v: RawVec<u8, _> = RawVec::with_capacity_in(16, &mut a);
+    unsafe { v.ptr().write_bytes(42, 16) };
+    assert_eq!(primary.live_allocations(), 1);
+    v.reserve(16, 16);
+    assert_eq!(primary.live_allocations(), 1);
+    v.reserve(32, 32);
+    assert_eq!(primary.live_allocations(), 0);
+    assert_eq!(secondary.live_allocations(), 1);
+    assert!((0..16).all(|i| unsafe { *v.ptr().add(i) } == 42));
+    drop(v);
+
+    let layout = Layout::new::<[u8; 24]>();
+    let (p1, _) = a.alloc(layout).unwrap();
+    let (p2, _) = a.alloc(layout).unwrap();
+    assert!(a.primary().owns(p1, layout));
+    assert!(!a.primary().owns(p2, layout));
+    assert!(a.owns(p2, layout));
+    unsafe {
+        a.dealloc(p2, layout);
+        a.dealloc(p1, layout);
+    }
+    assert_eq!(budget.used(), 0);
+}
+
+#[test]
+fn segregate_migrates_across_threshold() {
+    let small = LeakCheck::new(Global);
+    let large = LeakCheck::new(Global);
+    let mut a: Segregate<_, _, 32> = Segregate::new(small.clone(), large.clone());
+
+    let mut v: RawVec<u8, _> = RawVec::with_capacity_in(32, &mut a);
+    unsafe { v.ptr().write_bytes(42, 32) };
+    v.reserve(32, 32);
+    assert_eq!((small.live_allocations(), large.live_bytes()), (0, 64));
+    v.shrink_to_fit(16);
+    assert_eq!((small.live_bytes(), large.live_allocations()), (16, 0));
+    assert!((0..16).all(|i| unsafe { *v.ptr().add(i) } == 42));
+    drop(v);
+
+    let layout = Layout::new::<[u8; 32]>();
+    let (ptr, _) = a.alloc(layout).unwrap();
+    assert!(a.owns(ptr, layout));
+    unsafe {
+        assert!(a.grow_in_place(ptr, layout, 33).is_err());
+        a.dealloc(ptr, layout);
+    }
+}
+
+#[test]
+#[should_panic(expected = "threshold 16 is not larger than the previous threshold 64")]
+fn segregate_builder_checks_thresholds() {
+    SegregateBuilder::new(Global).above::<64, _>(Global).above::<16, _>(Global);
+}
@@ -0,0 +1,249 @@
+use core::fmt;
+use core::ptr::NonNull;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout};
+use crate::combinators::Owns;
+
+/// What is known about a live block: the layout it was last allocated or
+/// resized with, and the size returned by the allocator.
+#[derive(Clone, Copy)]
+struct Block {
+    layout: Layout,
+    use_max: usize,
+}
+
+/// An allocator wrapper validating that callers respect the `AllocRef`
+/// contract.
+///
+/// Every block returned by the wrapped allocator is recorded, along with
+/// the size it was returned with. Calls to `dealloc`, `realloc`,
+/// `grow_in_place` and `shrink_in_place` panic with a description of the
+/// problem when:
+///
+/// * the pointer is not currently allocated through this allocator (or one
+///   of its clones, which share the same records),
+/// * the layout doesn't *fit* the block, i.e. `layout.size()` is not in the
+///   `[use_min, use_max]` range, where `use_min` is the size most recently
+///   requested for the block and `use_max` the size the allocator returned,
+/// * `layout.align()` is not the alignment the block was allocated with,
+/// * the new size given to `grow_in_place` is smaller than `layout.size()`,
+///   or the one given to `shrink_in_place` is larger.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::alloc::{AllocRef, Layout};
+/// use allocator_api::debug::Checked;
+/// use allocator_api::Global;
+///
+/// let mut a = Checked::new(Global);
+/// let (ptr, _) = a.alloc(Layout::new::<[u32; 4]>()).unwrap();
+/// // This would panic, because the block was allocated with a size of 16:
+/// // unsafe { a.dealloc(ptr, Layout::new::<[u32; 2]>()) };
+/// unsafe { a.dealloc(ptr, Layout::new::<[u32; 4]>()) };
+/// # }
+/// ```
+pub struct Checked<A> {
+    a: A,
+    blocks: Arc<Mutex<HashMap<usize, Block>>>,
+}
+
+impl<A: AllocRef> Checked<A> {
+    /// Wraps the given allocator.
+    pub fn new(a: A) -> Self {
+        Checked { a, blocks: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    /// Returns a reference to the wrapped allocator.
+    pub fn get_ref(&self) -> &A {
+        &self.a
+    }
+
+    /// Consumes the wrapper, returning the wrapped allocator.
+    pub fn into_inner(self) -> A {
+        self.a
+    }
+
+    fn blocks(&self) -> MutexGuard<'_, HashMap<usize, Block>> {
+        // A panic while holding the lock doesn't leave the map in an
+        // inconsistent state.
+        self.blocks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record(&self, ptr: NonNull<u8>, layout: Layout, use_max: usize) {
+        self.blocks().insert(ptr.as_ptr() as usize, Block { layout, use_max });
+    }
+
+    /// Reallocates with `f`, after taking the record of `ptr` out, since a
+    /// clone may be given the same address as soon as `f` frees it.
+    unsafe fn realloc_with(
+        &mut self,
+        op: &str,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+        f: impl FnOnce(&mut A) -> Result<(NonNull<u8>, usize), AllocErr>,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.check_fit(op, ptr, layout);
+        let block = match self.blocks().remove(&(ptr.as_ptr() as usize)) {
+            Some(block) => block,
+            None => panic!("{}: {:p} is not currently allocated", op, ptr),
+        };
+        match f(&mut self.a) {
+            Ok((new_ptr, size)) => {
+                self.record(new_ptr, Layout::from_size_align_unchecked(new_size, layout.align()), size);
+                Ok((new_ptr, size))
+            }
+            Err(e) => {
+                // The block is still allocated.
+                self.blocks().insert(ptr.as_ptr() as usize, block);
+                Err(e)
+            }
+        }
+    }
+
+    /// Panics if `ptr` is not currently allocated, if `layout` doesn't fit
+    /// it, or if its alignment is not the one it was allocated with.
+    fn check_fit(&self, op: &str, ptr: NonNull<u8>, layout: Layout) {
+        let block = match self.blocks().get(&(ptr.as_ptr() as usize)) {
+            Some(block) => *block,
+            None => panic!("{}: {:p} is not currently allocated", op, ptr),
+        };
+        if layout.align() != block.layout.align() {
+            panic!(
+                "{}: alignment {} doesn't match {:p}, which was allocated with alignment {}",
+                op,
+                layout.align(),
+                ptr,
+                block.layout.align()
+            );
+        }
+        let use_min = block.layout.size();
+        if layout.size() < use_min || layout.size() > block.use_max {
+            panic!(
+                "{}: size {} doesn't fit {:p}, which must be used with a size between {} and {}",
+                op,
+                layout.size(),
+                ptr,
+                use_min,
+                block.use_max
+            );
+        }
+    }
+}
+
+impl<A: AllocRef + Clone> Clone for Checked<A> {
+    fn clone(&self) -> Self {
+        Checked { a: self.a.clone(), blocks: self.blocks.clone() }
+    }
+}
+
+impl<A: AllocRef + fmt::Debug> fmt::Debug for Checked<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Checked")
+            .field("a", &self.a)
+            .field("live_blocks", &self.blocks().len())
+            .finish()
+    }
+}
+
+unsafe impl<A: AllocRef> AllocRef for Checked<A> {
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let (ptr, size) = self.a.alloc(layout)?;
+        self.record(ptr, layout, size);
+        Ok((ptr, size))
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.check_fit("dealloc", ptr, layout);
+        self.blocks().remove(&(ptr.as_ptr() as usize));
+        self.a.dealloc(ptr, layout)
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        self.a.usable_size(layout)
+    }
+
+    fn alloc_zeroed(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let (ptr, size) = self.a.alloc_zeroed(layout)?;
+        self.record(ptr, layout, size);
+        Ok((ptr, size))
+    }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.realloc_with("realloc", ptr, layout, new_size, |a| a.realloc(ptr, layout, new_size))
+    }
+
+    unsafe fn realloc_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.realloc_with("realloc_zeroed", ptr, layout, new_size, |a| {
+            a.realloc_zeroed(ptr, layout, new_size)
+        })
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.check_fit("grow_in_place", ptr, layout);
+        if new_size < layout.size() {
+            panic!("grow_in_place: new size {} is smaller than {}", new_size, layout.size());
+        }
+        let size = self.a.grow_in_place(ptr, layout, new_size)?;
+        self.record(ptr, Layout::from_size_align_unchecked(new_size, layout.align()), size);
+        Ok(size)
+    }
+
+    unsafe fn grow_in_place_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.check_fit("grow_in_place_zeroed", ptr, layout);
+        if new_size < layout.size() {
+            panic!("grow_in_place_zeroed: new size {} is smaller than {}", new_size, layout.size());
+        }
+        let size = self.a.grow_in_place_zeroed(ptr, layout, new_size)?;
+        self.record(ptr, Layout::from_size_align_unchecked(new_size, layout.align()), size);
+        Ok(size)
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.check_fit("shrink_in_place", ptr, layout);
+        if new_size > layout.size() {
+            panic!("shrink_in_place: new size {} is larger than {}", new_size, layout.size());
+        }
+        let size = self.a.shrink_in_place(ptr, layout, new_size)?;
+        self.record(ptr, Layout::from_size_align_unchecked(new_size, layout.align()), size);
+        Ok(size)
+    }
+}
+
+impl<A: AllocRef> Owns for Checked<A> {
+    /// Returns whether `ptr` is currently allocated.
+    fn owns(&self, ptr: NonNull<u8>, _layout: Layout) -> bool {
+        self.blocks().contains_key(&(ptr.as_ptr() as usize))
+    }
+}
@@ -0,0 +1,154 @@
+use core::ptr::{self, NonNull};
+
+use crate::alloc::{AllocErr, AllocRef, Layout};
+
+/// Which side of allocations [`GuardPage`] protects.
+///
+/// [`GuardPage`]: struct.GuardPage.html
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GuardSide {
+    /// Allocations end right before an inaccessible page, catching accesses
+    /// past their end.
+    ///
+    /// When the allocation size is not a multiple of its alignment, there
+    /// is padding between the end of the allocation and the guard page,
+    /// and overruns into that padding are not caught.
+    Overflow,
+    /// Allocations start right after an inaccessible page, catching
+    /// accesses before their start.
+    Underflow,
+}
+
+/// A debugging allocator placing each allocation against an inaccessible
+/// page, so that out-of-bounds accesses fault immediately.
+///
+/// Each allocation gets its own memory mapping, made of as many pages as
+/// necessary to hold it, plus a guard page on the configured [`GuardSide`].
+/// This makes this allocator very wasteful, and only suitable for
+/// debugging.
+///
+/// In quarantine mode, deallocated memory is made inaccessible instead of
+/// being unmapped, so that its address range is never reused and any
+/// use-after-free faults. Without quarantine, use-after-free faults until
+/// the address range is reused by a subsequent mapping.
+///
+/// Alignments larger than the page size are not supported.
+///
+/// [`GuardSide`]: enum.GuardSide.html
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::debug::GuardPage;
+/// use allocator_api::RawVec;
+///
+/// let mut v: RawVec<u32, _> = RawVec::with_capacity_in(10, GuardPage::new().quarantine());
+/// unsafe {
+///     v.ptr().add(9).write(42);
+///     // This would fault:
+///     // v.ptr().add(10).write(42);
+/// }
+/// # }
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct GuardPage {
+    side: GuardSide,
+    quarantine: bool,
+}
+
+impl Default for GuardPage {
+    fn default() -> Self {
+        GuardPage::new()
+    }
+}
+
+impl GuardPage {
+    /// Creates an allocator catching overflows, without quarantine.
+    pub const fn new() -> Self {
+        GuardPage { side: GuardSide::Overflow, quarantine: false }
+    }
+
+    /// Sets which side of allocations is protected.
+    pub const fn side(mut self, side: GuardSide) -> Self {
+        self.side = side;
+        self
+    }
+
+    /// Enables quarantine mode.
+    pub const fn quarantine(mut self) -> Self {
+        self.quarantine = true;
+        self
+    }
+
+    /// Returns the address and length of the mapping holding an allocation
+    /// of the given size starting at `ptr`.
+    fn mapping(&self, ptr: NonNull<u8>, size: usize, page: usize) -> (*mut u8, usize) {
+        let data_len = (size + page - 1) & !(page - 1);
+        let base = match self.side {
+            GuardSide::Overflow => (ptr.as_ptr() as usize) & !(page - 1),
+            GuardSide::Underflow => ptr.as_ptr() as usize - page,
+        };
+        (base as *mut u8, data_len + page)
+    }
+}
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+unsafe impl AllocRef for GuardPage {
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let page = page_size();
+        let size = layout.size();
+        if layout.align() > page {
+            return Err(AllocErr);
+        }
+        let data_len = size.checked_add(page - 1).ok_or(AllocErr)? & !(page - 1);
+        let len = data_len.checked_add(page).ok_or(AllocErr)?;
+        unsafe {
+            let base = libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if base == libc::MAP_FAILED {
+                return Err(AllocErr);
+            }
+            let base = base as *mut u8;
+            let (guard, ptr) = match self.side {
+                GuardSide::Overflow => {
+                    let offset = (data_len - size) & !(layout.align() - 1);
+                    (base.add(data_len), base.add(offset))
+                }
+                GuardSide::Underflow => (base, base.add(page)),
+            };
+            if libc::mprotect(guard as *mut libc::c_void, page, libc::PROT_NONE) != 0 {
+                libc::munmap(base as *mut libc::c_void, len);
+                return Err(AllocErr);
+            }
+            Ok((NonNull::new_unchecked(ptr), size))
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let (base, len) = self.mapping(ptr, layout.size(), page_size());
+        let base = base as *mut libc::c_void;
+        if self.quarantine {
+            // Keep the address range reserved, but release the memory.
+            libc::mprotect(base, len, libc::PROT_NONE);
+            libc::madvise(base, len, libc::MADV_DONTNEED);
+        } else {
+            libc::munmap(base, len);
+        }
+    }
+
+    fn alloc_zeroed(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        // Fresh anonymous mappings are zeroed.
+        self.alloc(layout)
+    }
+}
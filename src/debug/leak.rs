@@ -0,0 +1,315 @@
+use core::fmt;
+use core::ptr::NonNull;
+
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::collections::HashMap;
+use std::string::String;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::{eprintln, thread};
+
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout};
+use crate::combinators::Owns;
+
+/// What to do when leaks are found as a [`LeakCheck`] is dropped.
+///
+/// [`LeakCheck`]: struct.LeakCheck.html
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OnLeak {
+    /// Panic with a description of the leaked allocations.
+    Panic,
+    /// Print a description of the leaked allocations on standard error.
+    Report,
+    /// Do nothing.
+    Ignore,
+}
+
+struct Record {
+    layout: Layout,
+    backtrace: Option<Backtrace>,
+}
+
+struct Registry {
+    live: Mutex<HashMap<usize, Record>>,
+    on_leak: OnLeak,
+}
+
+/// An allocator wrapper keeping track of all the live allocations, in order
+/// to detect leaks.
+///
+/// Clones of a `LeakCheck` share the same set of live allocations. When the
+/// last of them is dropped, allocations that are still live are reported
+/// according to the [`OnLeak`] setting given to [`new`] or
+/// [`with_on_leak`]. Containers leaked with e.g. `Box::leak` or
+/// `mem::forget` never drop their handle, so use [`assert_no_leaks`] to
+/// check for leaks while they are around.
+///
+/// Deallocating or reallocating a pointer that is not a live allocation,
+/// e.g. because it was already deallocated, panics.
+///
+/// When backtraces are enabled (see [`Backtrace::capture`]), each allocation
+/// records the backtrace leading to it, which is included in reports.
+///
+/// [`new`]: #method.new
+/// [`with_on_leak`]: #method.with_on_leak
+/// [`assert_no_leaks`]: #method.assert_no_leaks
+/// [`OnLeak`]: enum.OnLeak.html
+/// [`Backtrace::capture`]: https://doc.rust-lang.org/std/backtrace/struct.Backtrace.html#method.capture
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::debug::LeakCheck;
+/// use allocator_api::{Box, Global};
+///
+/// let a = LeakCheck::new(Global);
+/// let b = Box::new_in(42, a.clone());
+/// let leaked = Box::into_raw(b);
+/// assert_eq!(a.live_allocations(), 1);
+///
+/// drop(unsafe { Box::from_raw_in(leaked, a.clone()) });
+/// a.assert_no_leaks();
+/// # }
+/// ```
+pub struct LeakCheck<A: AllocRef> {
+    a: A,
+    registry: Arc<Registry>,
+}
+
+impl<A: AllocRef> LeakCheck<A> {
+    /// Wraps the given allocator, panicking on leaks when the returned
+    /// handle and all its clones are dropped.
+    pub fn new(a: A) -> Self {
+        LeakCheck::with_on_leak(a, OnLeak::Panic)
+    }
+
+    /// Wraps the given allocator, handling leaks as given when the returned
+    /// handle and all its clones are dropped.
+    pub fn with_on_leak(a: A, on_leak: OnLeak) -> Self {
+        let registry = Registry { live: Mutex::new(HashMap::new()), on_leak };
+        LeakCheck { a, registry: Arc::new(registry) }
+    }
+
+    /// Returns the number of live allocations.
+    pub fn live_allocations(&self) -> usize {
+        self.live().len()
+    }
+
+    /// Returns the number of bytes in live allocations.
+    pub fn live_bytes(&self) -> usize {
+        self.live().values().map(|r| r.layout.size()).sum()
+    }
+
+    /// Panics if there are any live allocations.
+    ///
+    /// The panic message describes the live allocations, including their
+    /// backtrace when available.
+    pub fn assert_no_leaks(&self) {
+        if let Some(report) = self.registry.report() {
+            panic!("{}", report);
+        }
+    }
+
+    fn live(&self) -> MutexGuard<'_, HashMap<usize, Record>> {
+        // A panic while holding the lock doesn't leave the map in an
+        // inconsistent state.
+        self.registry.live.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn track(
+        &self,
+        result: Result<(NonNull<u8>, usize), AllocErr>,
+        layout: Layout,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        if let Ok((ptr, _)) = result {
+            let backtrace = Backtrace::capture();
+            let backtrace = match backtrace.status() {
+                BacktraceStatus::Captured => Some(backtrace),
+                _ => None,
+            };
+            self.live().insert(ptr.as_ptr() as usize, Record { layout, backtrace });
+        }
+        result
+    }
+
+    /// Stops tracking `ptr`, which must be a live allocation, before it is
+    /// given back to the wrapped allocator. A clone may be given the same
+    /// address as soon as it is, and track it.
+    fn untrack(&self, ptr: NonNull<u8>) -> Record {
+        match self.live().remove(&(ptr.as_ptr() as usize)) {
+            Some(record) => record,
+            None => panic!("{:p} is not a live allocation", ptr),
+        }
+    }
+
+    unsafe fn realloc_with(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+        f: impl FnOnce(&mut A) -> Result<(NonNull<u8>, usize), AllocErr>,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let record = self.untrack(ptr);
+        let result = f(&mut self.a);
+        if result.is_err() {
+            // The block is still live.
+            self.live().insert(ptr.as_ptr() as usize, record);
+        }
+        self.track(result, Layout::from_size_align_unchecked(new_size, layout.align()))
+    }
+
+    fn resize(&self, ptr: NonNull<u8>, layout: Layout, new_size: usize) {
+        if let Some(record) = self.live().get_mut(&(ptr.as_ptr() as usize)) {
+            record.layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        }
+    }
+}
+
+impl Registry {
+    fn report(&self) -> Option<String> {
+        use core::fmt::Write;
+
+        let live = self.live.lock().unwrap_or_else(|e| e.into_inner());
+        if live.is_empty() {
+            return None;
+        }
+        let mut report = String::new();
+        let bytes: usize = live.values().map(|r| r.layout.size()).sum();
+        let _ = writeln!(report, "{} allocation(s) leaked, for {} bytes:", live.len(), bytes);
+        let mut live: std::vec::Vec<_> = live.iter().collect();
+        live.sort_by_key(|(ptr, _)| **ptr);
+        for (ptr, record) in live {
+            let _ = writeln!(
+                report,
+                "  {:#x}: size {}, align {}",
+                ptr,
+                record.layout.size(),
+                record.layout.align()
+            );
+            if let Some(backtrace) = &record.backtrace {
+                let _ = writeln!(report, "{}", backtrace);
+            }
+        }
+        Some(report)
+    }
+}
+
+impl<A: AllocRef + Clone> Clone for LeakCheck<A> {
+    fn clone(&self) -> Self {
+        LeakCheck { a: self.a.clone(), registry: self.registry.clone() }
+    }
+}
+
+// The registry is shared by all the clones of a `LeakCheck`, so it is
+// dropped along with the last of them.
+impl Drop for Registry {
+    fn drop(&mut self) {
+        let report = match self.on_leak {
+            OnLeak::Ignore => return,
+            _ => match self.report() {
+                Some(report) => report,
+                None => return,
+            },
+        };
+        if self.on_leak == OnLeak::Panic && !thread::panicking() {
+            panic!("{}", report);
+        } else {
+            eprintln!("{}", report);
+        }
+    }
+}
+
+impl<A: AllocRef + fmt::Debug> fmt::Debug for LeakCheck<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LeakCheck")
+            .field("a", &self.a)
+            .field("live_allocations", &self.live_allocations())
+            .finish()
+    }
+}
+
+unsafe impl<A: AllocRef> AllocRef for LeakCheck<A> {
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let result = self.a.alloc(layout);
+        self.track(result, layout)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.untrack(ptr);
+        self.a.dealloc(ptr, layout)
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        self.a.usable_size(layout)
+    }
+
+    fn alloc_zeroed(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let result = self.a.alloc_zeroed(layout);
+        self.track(result, layout)
+    }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.realloc_with(ptr, layout, new_size, |a| a.realloc(ptr, layout, new_size))
+    }
+
+    unsafe fn realloc_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.realloc_with(ptr, layout, new_size, |a| a.realloc_zeroed(ptr, layout, new_size))
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let result = self.a.grow_in_place(ptr, layout, new_size);
+        if result.is_ok() {
+            self.resize(ptr, layout, new_size);
+        }
+        result
+    }
+
+    unsafe fn grow_in_place_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let result = self.a.grow_in_place_zeroed(ptr, layout, new_size);
+        if result.is_ok() {
+            self.resize(ptr, layout, new_size);
+        }
+        result
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let result = self.a.shrink_in_place(ptr, layout, new_size);
+        if result.is_ok() {
+            self.resize(ptr, layout, new_size);
+        }
+        result
+    }
+}
+
+impl<A: AllocRef> Owns for LeakCheck<A> {
+    /// Returns whether `ptr` is a live allocation.
+    fn owns(&self, ptr: NonNull<u8>, _layout: Layout) -> bool {
+        self.live().contains_key(&(ptr.as_ptr() as usize))
+    }
+}
@@ -0,0 +1,20 @@
+//! Allocator wrappers helping to debug memory management issues.
+
+#[cfg(feature = "std")]
+mod checked;
+#[cfg(target_os = "linux")]
+mod guard_page;
+#[cfg(feature = "std")]
+mod leak;
+mod poison;
+
+#[cfg(feature = "std")]
+pub use self::checked::Checked;
+#[cfg(target_os = "linux")]
+pub use self::guard_page::{GuardPage, GuardSide};
+#[cfg(feature = "std")]
+pub use self::leak::{LeakCheck, OnLeak};
+pub use self::poison::{Poison, CANARY_PATTERN, FREED_PATTERN, FRESH_PATTERN};
+
+#[cfg(all(test, feature = "std"))]
+mod tests;
@@ -0,0 +1,271 @@
+use core::cmp;
+use core::mem;
+use core::ptr::{self, NonNull};
+use core::slice;
+
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout};
+use crate::combinators::Owns;
+
+/// Byte pattern filling fresh allocations made through [`Poison`].
+///
+/// [`Poison`]: struct.Poison.html
+pub const FRESH_PATTERN: u8 = 0xcd;
+
+/// Byte pattern filling memory deallocated through [`Poison`].
+///
+/// [`Poison`]: struct.Poison.html
+pub const FREED_PATTERN: u8 = 0xdd;
+
+/// Byte pattern of the canaries surrounding allocations made through
+/// [`Poison`].
+///
+/// [`Poison`]: struct.Poison.html
+pub const CANARY_PATTERN: u8 = 0xfd;
+
+const CANARY_SIZE: usize = 16;
+
+// The header holds the size and alignment of the allocation, followed by
+// the head canary. The header is placed right before the allocation, so that
+// it can be found without knowing the alignment, and any padding before it
+// is part of the head canary.
+const META_SIZE: usize = 2 * mem::size_of::<usize>();
+const HEADER_SIZE: usize = META_SIZE + CANARY_SIZE;
+
+/// An allocator wrapper poisoning memory and checking canaries around
+/// allocations, to detect uses of uninitialized or freed memory, and buffer
+/// overruns.
+///
+/// * Fresh allocations are filled with [`FRESH_PATTERN`], except when
+///   allocated with `alloc_zeroed`.
+/// * Deallocated memory is filled with [`FREED_PATTERN`].
+/// * Each allocation is enlarged to be surrounded by canaries filled with
+///   [`CANARY_PATTERN`], which are checked on deallocation and reallocation.
+///
+/// The layout given to `dealloc`, `realloc` and the in-place methods is also
+/// checked against the one used to allocate the block. Because allocations
+/// are immediately followed by a canary, the usable size of allocations is
+/// exactly the requested size, and deallocation must use that size.
+///
+/// Any check failure panics.
+///
+/// [`FRESH_PATTERN`]: constant.FRESH_PATTERN.html
+/// [`FREED_PATTERN`]: constant.FREED_PATTERN.html
+/// [`CANARY_PATTERN`]: constant.CANARY_PATTERN.html
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::debug::{Poison, FRESH_PATTERN};
+/// use allocator_api::{Global, RawVec};
+///
+/// let v: RawVec<u8, _> = RawVec::with_capacity_in(10, Poison::new(Global));
+/// unsafe {
+///     assert_eq!(*v.ptr().add(9), FRESH_PATTERN);
+///     // This would make dropping `v` panic:
+///     // v.ptr().add(10).write(0);
+/// }
+/// # }
+/// ```
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Poison<A> {
+    a: A,
+}
+
+impl<A: AllocRef> Poison<A> {
+    /// Wraps the given allocator.
+    pub fn new(a: A) -> Self {
+        Poison { a }
+    }
+
+    /// Returns a reference to the wrapped allocator.
+    pub fn get_ref(&self) -> &A {
+        &self.a
+    }
+
+    /// Consumes the wrapper, returning the wrapped allocator.
+    pub fn into_inner(self) -> A {
+        self.a
+    }
+}
+
+/// Returns the size of the area before the allocation, which is the header
+/// padded to keep the allocation aligned.
+fn head_size(align: usize) -> usize {
+    (HEADER_SIZE + align - 1) & !(align - 1)
+}
+
+/// Returns the layout of the block holding an allocation of `size` bytes
+/// and alignment `align`, along with its canaries.
+fn block_layout(size: usize, align: usize) -> Option<Layout> {
+    let block_size = head_size(align).checked_add(size)?.checked_add(CANARY_SIZE)?;
+    Layout::from_size_align(block_size, align).ok()
+}
+
+unsafe fn block_start(ptr: NonNull<u8>, align: usize) -> NonNull<u8> {
+    NonNull::new_unchecked(ptr.as_ptr().sub(head_size(align)))
+}
+
+/// Returns a pointer to the size and alignment of the allocation at `ptr`.
+unsafe fn meta(ptr: NonNull<u8>) -> *mut usize {
+    ptr.as_ptr().sub(HEADER_SIZE) as *mut usize
+}
+
+/// Writes the header and the canaries around an allocation of `size` bytes
+/// at `ptr`.
+unsafe fn write_guards(ptr: NonNull<u8>, size: usize, align: usize) {
+    let start = block_start(ptr, align).as_ptr();
+    let meta = meta(ptr);
+    ptr::write_bytes(start, CANARY_PATTERN, meta as usize - start as usize);
+    meta.write_unaligned(size);
+    meta.add(1).write_unaligned(align);
+    ptr::write_bytes(ptr.as_ptr().sub(CANARY_SIZE), CANARY_PATTERN, CANARY_SIZE);
+    ptr::write_bytes(ptr.as_ptr().add(size), CANARY_PATTERN, CANARY_SIZE);
+}
+
+/// Checks the header and canaries around the allocation at `ptr`, and that
+/// it was allocated with the given layout.
+unsafe fn check_guards(ptr: NonNull<u8>, layout: Layout) {
+    let align = layout.align();
+    // The header is at a fixed offset from `ptr`, the start of the block
+    // can only be derived from the alignment once it is known to be right.
+    let meta = meta(ptr);
+    let (size, alloc_align) = (meta.read_unaligned(), meta.add(1).read_unaligned());
+    if (size, alloc_align) != (layout.size(), align) {
+        panic!(
+            "AllocRef contract violation: block {:p} was allocated with size {} and alignment {}, \
+             but is used with size {} and alignment {}",
+            ptr,
+            size,
+            alloc_align,
+            layout.size(),
+            align,
+        );
+    }
+    let start = block_start(ptr, align).as_ptr();
+    let padding = slice::from_raw_parts(start, meta as usize - start as usize);
+    let head = slice::from_raw_parts(ptr.as_ptr().sub(CANARY_SIZE), CANARY_SIZE);
+    if padding.iter().chain(head).any(|&b| b != CANARY_PATTERN) {
+        panic!("Heap corruption: the head canary of block {:p} was overwritten", ptr);
+    }
+    let tail = slice::from_raw_parts(ptr.as_ptr().add(size), CANARY_SIZE);
+    if tail.iter().any(|&b| b != CANARY_PATTERN) {
+        panic!("Heap corruption: the tail canary of block {:p} was overwritten", ptr);
+    }
+}
+
+impl<A: AllocRef> Poison<A> {
+    fn alloc_impl(&mut self, layout: Layout, zeroed: bool) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let size = layout.size();
+        let align = layout.align();
+        let block = block_layout(size, align).ok_or(AllocErr)?;
+        let (block, _) = if zeroed { self.a.alloc_zeroed(block)? } else { self.a.alloc(block)? };
+        unsafe {
+            let ptr = NonNull::new_unchecked(block.as_ptr().add(head_size(align)));
+            if !zeroed {
+                ptr::write_bytes(ptr.as_ptr(), FRESH_PATTERN, size);
+            }
+            write_guards(ptr, size, align);
+            Ok((ptr, size))
+        }
+    }
+}
+
+unsafe impl<A: AllocRef> AllocRef for Poison<A> {
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.alloc_impl(layout, false)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        check_guards(ptr, layout);
+        let block = block_layout(layout.size(), layout.align()).unwrap();
+        let start = block_start(ptr, layout.align());
+        ptr::write_bytes(start.as_ptr(), FREED_PATTERN, block.size());
+        self.a.dealloc(start, block)
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        // The guards are placed right after the requested size, so blocks must
+        // be given back with exactly that size.
+        (layout.size(), layout.size())
+    }
+
+    fn alloc_zeroed(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.alloc_impl(layout, true)
+    }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        check_guards(ptr, layout);
+        let old_size = layout.size();
+
+        if new_size > old_size {
+            if let Ok(size) = self.grow_in_place(ptr, layout, new_size) {
+                return Ok((ptr, size));
+            }
+        } else if new_size < old_size {
+            if let Ok(size) = self.shrink_in_place(ptr, layout, new_size) {
+                return Ok((ptr, size));
+            }
+        } else {
+            return Ok((ptr, new_size));
+        }
+
+        // Don't let the wrapped allocator move the block, so that the old
+        // block is poisoned on deallocation.
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let result = self.alloc(new_layout);
+        if let Ok((new_ptr, _)) = result {
+            ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), cmp::min(old_size, new_size));
+            self.dealloc(ptr, layout);
+        }
+        result
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        check_guards(ptr, layout);
+        let align = layout.align();
+        let block = block_layout(layout.size(), align).unwrap();
+        let new_block = block_layout(new_size, align).ok_or(CannotReallocInPlace)?;
+        self.a.grow_in_place(block_start(ptr, align), block, new_block.size())?;
+        let tail = ptr.as_ptr().add(layout.size());
+        ptr::write_bytes(tail, FRESH_PATTERN, new_size - layout.size());
+        write_guards(ptr, new_size, align);
+        Ok(new_size)
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        check_guards(ptr, layout);
+        let align = layout.align();
+        let block = block_layout(layout.size(), align).unwrap();
+        let new_block = block_layout(new_size, align).unwrap();
+        self.a.shrink_in_place(block_start(ptr, align), block, new_block.size())?;
+        write_guards(ptr, new_size, align);
+        Ok(new_size)
+    }
+}
+
+impl<A: Owns> Owns for Poison<A> {
+    fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        let start = (ptr.as_ptr() as usize).wrapping_sub(head_size(layout.align()));
+        match (NonNull::new(start as *mut u8), block_layout(layout.size(), layout.align())) {
+            (Some(start), Some(block)) => self.a.owns(start, block),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use super::*;
+use crate::alloc::{AllocErr, AllocRef, Global, Layout};
+use crate::boxed::Box;
+use crate::raw_vec::RawVec;
+use crate::testing::{FailingAlloc, Faults, Ops};
+
+use core::ptr::NonNull;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+type Callback = std::boxed::Box<dyn FnOnce()>;
+
+/// An allocator whose `realloc` lets a callback allocate before returning,
+/// like another thread could, and gives it the address it just freed.
+#[derive(Clone, Default)]
+struct Reusing {
+    freed: Rc<Cell<Option<NonNull<u8>>>>,
+    on_move: Rc<RefCell<Option<Callback>>>,
+}
+
+impl Reusing {
+    fn on_move(&self, f: impl FnOnce() + 'static) {
+        *self.on_move.borrow_mut() = Some(std::boxed::Box::new(f));
+    }
+}
+
+unsafe impl AllocRef for Reusing {
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        match self.freed.take() {
+            Some(ptr) => Ok((ptr, layout.size())),
+            None => Global.alloc(layout),
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        Global.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let (new_ptr, size) = Global.alloc(new_layout)?;
+        // Keep the old block around for the next allocation of the same
+        // layout, instead of freeing it.
+        self.freed.set(Some(ptr));
+        let f = self.on_move.borrow_mut().take();
+        if let Some(f) = f {
+            f();
+        }
+        Ok((new_ptr, size))
+    }
+}
+
+#[test]
+#[should_panic(expected = "1 allocation(s) leaked, for 4 bytes")]
+fn leak_check_panics_on_drop() {
+    let a = LeakCheck::new(Global);
+    let mut b = a.clone();
+    b.alloc(Layout::new::<u32>()).unwrap();
+    drop(a);
+    drop(b);
+}
+
+#[test]
+fn leak_check_waits_for_last_handle() {
+    let a = LeakCheck::new(Global);
+    let b = Box::new_in(42u32, a.clone());
+    let c = a.clone();
+    // The allocation is still live, but `b` and `c` are still around.
+    drop(a);
+    assert_eq!(c.live_allocations(), 1);
+    drop(b);
+    c.assert_no_leaks();
+}
+
+#[test]
+fn leak_check_tracks_reallocations() {
+    let a = LeakCheck::with_on_leak(Global, OnLeak::Ignore);
+    let mut v: RawVec<u8, _> = RawVec::with_capacity_in(16, a.clone());
+    v.reserve(16, 48);
+    assert_eq!(a.live_allocations(), 1);
+    assert_eq!(a.live_bytes(), 64);
+    v.shrink_to_fit(8);
+    assert_eq!(a.live_bytes(), 8);
+    drop(v);
+    a.assert_no_leaks();
+
+    core::mem::forget(RawVec::<u8, _>::with_capacity_in(10, a.clone()));
+    assert_eq!(a.live_allocations(), 1);
+}
+
+#[test]
+fn leak_check_untracks_before_realloc() {
+    let inner = Reusing::default();
+    let mut a = LeakCheck::new(inner.clone());
+    let layout = Layout::new::<u64>();
+    let (ptr, _) = a.alloc(layout).unwrap();
+    let mut other = a.clone();
+    let reused = Rc::new(Cell::new(None));
+    let r = reused.clone();
+    inner.on_move(move || r.set(Some(other.alloc(layout).unwrap().0)));
+    let (new_ptr, _) = unsafe { a.realloc(ptr, layout, 64) }.unwrap();
+    // The block allocated at the old address while `realloc` was running
+    // is still tracked.
+    assert_eq!(reused.get(), Some(ptr));
+    assert_eq!(a.live_allocations(), 2);
+    unsafe {
+        a.dealloc(ptr, layout);
+        a.dealloc(new_ptr, Layout::from_size_align(64, 8).unwrap());
+    }
+    a.assert_no_leaks();
+}
+
+#[test]
+fn leak_check_keeps_tracking_after_failed_realloc() {
+    let faults = Faults::nth(0).only(Ops::REALLOC);
+    let mut a = LeakCheck::new(FailingAlloc::new(Global, &faults));
+    let layout = Layout::new::<u64>();
+    let (ptr, _) = a.alloc(layout).unwrap();
+    assert_eq!(unsafe { a.realloc(ptr, layout, 64) }, Err(AllocErr));
+    assert_eq!(a.live_allocations(), 1);
+    unsafe { a.dealloc(ptr, layout) };
+}
+
+#[cfg(target_os = "linux")]
+fn faults(f: impl FnOnce()) -> bool {
+    unsafe {
+        let pid = libc::fork();
+        if pid == 0 {
+            f();
+            libc::_exit(0);
+        }
+        let mut status = 0;
+        libc::waitpid(pid, &mut status, 0);
+        libc::WIFSIGNALED(status) && libc::WTERMSIG(status) == libc::SIGSEGV
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn guard_page() {
+    for &side in &[GuardSide::Overflow, GuardSide::Underflow] {
+        for &quarantine in &[false, true] {
+            let mut a = GuardPage::new().side(side);
+            if quarantine {
+                a = a.quarantine();
+            }
+            let mut v: RawVec<u32, _> = RawVec::with_capacity_in(10, a);
+            let p = v.ptr();
+            unsafe {
+                p.write(1);
+                p.add(9).write(1);
+            }
+            let overflow = faults(|| unsafe { p.add(10).write_volatile(1) });
+            let underflow = faults(|| unsafe { p.sub(1).write_volatile(1) });
+            assert_eq!(overflow, side == GuardSide::Overflow);
+            assert_eq!(underflow, side == GuardSide::Underflow);
+            v.reserve(10, 100);
+            let use_after_free = faults(|| unsafe { p.write_volatile(1) });
+            assert!(use_after_free || !quarantine);
+        }
+    }
+}
+
+#[test]
+fn poison_fills_and_keeps_contents() {
+    let mut v: RawVec<u8, _> = RawVec::with_capacity_in(16, Poison::new(Global));
+    unsafe {
+        assert!((0..16).all(|i| *v.ptr().add(i) == FRESH_PATTERN));
+        v.ptr().write_bytes(1, 16);
+    }
+    v.reserve(16, 48);
+    unsafe {
+        assert!((0..16).all(|i| *v.ptr().add(i) == 1));
+        assert!((16..64).all(|i| *v.ptr().add(i) == FRESH_PATTERN));
+    }
+    v.shrink_to_fit(8);
+    assert_eq!(v.capacity(), 8);
+
+    let v: RawVec<u8, _> = RawVec::with_capacity_zeroed_in(16, Poison::new(Global));
+    unsafe {
+        assert!((0..16).all(|i| *v.ptr().add(i) == 0));
+    }
+}
+
+#[test]
+#[should_panic(expected = "tail canary")]
+fn poison_detects_overflow() {
+    let v: RawVec<u32, _> = RawVec::with_capacity_in(4, Poison::new(Global));
+    unsafe { v.ptr().add(4).write(0) };
+}
+
+#[test]
+#[should_panic(expected = "contract violation")]
+fn poison_detects_layout_mismatch() {
+    let mut a = Poison::new(Global);
+    unsafe {
+        let (ptr, _) = a.alloc(Layout::new::<u64>()).unwrap();
+        a.dealloc(ptr, Layout::new::<u32>());
+    }
+}
+
+#[test]
+#[should_panic(expected = "allocated with size 8 and alignment 8")]
+fn poison_detects_alignment_mismatch() {
+    let mut a = Poison::new(Global);
+    unsafe {
+        let (ptr, _) = a.alloc(Layout::from_size_align(8, 8).unwrap()).unwrap();
+        a.dealloc(ptr, Layout::from_size_align(8, 64).unwrap());
+    }
+}
+
+#[test]
+fn checked_accepts_fitting_layouts() {
+    let mut v: RawVec<u8, _> = RawVec::with_capacity_in(16, Checked::new(Global));
+    v.reserve(16, 48);
+    v.shrink_to_fit(8);
+    drop(v);
+}
+
+#[test]
+fn checked_forgets_blocks_before_realloc() {
+    let inner = Reusing::default();
+    let mut a = Checked::new(inner.clone());
+    let layout = Layout::new::<u64>();
+    let (ptr, _) = a.alloc(layout).unwrap();
+    let mut other = a.clone();
+    inner.on_move(move || assert_eq!(other.alloc(layout).unwrap().0, ptr));
+    let (new_ptr, _) = unsafe { a.realloc(ptr, layout, 64) }.unwrap();
+    // The block allocated at the old address while `realloc` was running
+    // is still recorded.
+    unsafe {
+        a.dealloc(ptr, layout);
+        a.dealloc(new_ptr, Layout::from_size_align(64, 8).unwrap());
+    }
+}
+
+#[test]
+fn checked_keeps_blocks_after_failed_realloc() {
+    let faults = Faults::nth(0).only(Ops::REALLOC);
+    let mut a = Checked::new(FailingAlloc::new(Global, &faults));
+    let layout = Layout::new::<u64>();
+    let (ptr, _) = a.alloc(layout).unwrap();
+    assert_eq!(unsafe { a.realloc(ptr, layout, 64) }, Err(AllocErr));
+    unsafe { a.dealloc(ptr, layout) };
+}
+
+#[test]
+#[should_panic(expected = "dealloc: alignment 4 doesn't match")]
+fn checked_detects_wrong_alignment() {
+    let mut a = Checked::new(Global);
+    unsafe {
+        let (ptr, _) = a.alloc(Layout::from_size_align(8, 8).unwrap()).unwrap();
+        a.dealloc(ptr, Layout::from_size_align(8, 4).unwrap());
+    }
+}
+
+#[test]
+#[should_panic(expected = "dealloc: size 4 doesn't fit")]
+fn checked_detects_wrong_size() {
+    let mut a = Checked::new(Global);
+    unsafe {
+        let (ptr, _) = a.alloc(Layout::new::<u64>()).unwrap();
+        a.dealloc(ptr, Layout::from_size_align(4, 8).unwrap());
+    }
+}
+
+#[test]
+#[should_panic(expected = "is not currently allocated")]
+fn checked_detects_double_free() {
+    let mut a = Checked::new(Global);
+    unsafe {
+        let (ptr, _) = a.alloc(Layout::new::<u64>()).unwrap();
+        a.dealloc(ptr, Layout::new::<u64>());
+        a.dealloc(ptr, Layout::new::<u64>());
+    }
+}
+
+#[test]
+#[should_panic(expected = "shrink_in_place: new size 16 is larger than 8")]
+fn checked_detects_bad_shrink() {
+    let mut a = Checked::new(Global);
+    unsafe {
+        let (ptr, _) = a.alloc(Layout::new::<u64>()).unwrap();
+        let _ = a.shrink_in_place(ptr, Layout::new::<u64>(), 16);
+    }
+}
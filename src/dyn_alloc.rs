@@ -0,0 +1,185 @@
+//! Type-erased allocator handles.
+
+use core::fmt;
+use core::ptr::NonNull;
+
+#[cfg(feature = "std")]
+use crate::alloc::Global;
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout};
+#[cfg(feature = "std")]
+use crate::boxed::Box;
+
+macro_rules! forward_alloc_ref {
+    ($($field:tt)*) => {
+        #[inline]
+        fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+            self.$($field)*.alloc(layout)
+        }
+
+        #[inline]
+        unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+            self.$($field)*.dealloc(ptr, layout)
+        }
+
+        #[inline]
+        fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+            self.$($field)*.usable_size(layout)
+        }
+
+        #[inline]
+        fn alloc_zeroed(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+            self.$($field)*.alloc_zeroed(layout)
+        }
+
+        #[inline]
+        unsafe fn realloc(
+            &mut self,
+            ptr: NonNull<u8>,
+            layout: Layout,
+            new_size: usize,
+        ) -> Result<(NonNull<u8>, usize), AllocErr> {
+            self.$($field)*.realloc(ptr, layout, new_size)
+        }
+
+        #[inline]
+        unsafe fn realloc_zeroed(
+            &mut self,
+            ptr: NonNull<u8>,
+            layout: Layout,
+            new_size: usize,
+        ) -> Result<(NonNull<u8>, usize), AllocErr> {
+            self.$($field)*.realloc_zeroed(ptr, layout, new_size)
+        }
+
+        #[inline]
+        unsafe fn grow_in_place(
+            &mut self,
+            ptr: NonNull<u8>,
+            layout: Layout,
+            new_size: usize,
+        ) -> Result<usize, CannotReallocInPlace> {
+            self.$($field)*.grow_in_place(ptr, layout, new_size)
+        }
+
+        #[inline]
+        unsafe fn grow_in_place_zeroed(
+            &mut self,
+            ptr: NonNull<u8>,
+            layout: Layout,
+            new_size: usize,
+        ) -> Result<usize, CannotReallocInPlace> {
+            self.$($field)*.grow_in_place_zeroed(ptr, layout, new_size)
+        }
+
+        #[inline]
+        unsafe fn shrink_in_place(
+            &mut self,
+            ptr: NonNull<u8>,
+            layout: Layout,
+            new_size: usize,
+        ) -> Result<usize, CannotReallocInPlace> {
+            self.$($field)*.shrink_in_place(ptr, layout, new_size)
+        }
+    };
+}
+
+/// A borrowed allocator whose type has been erased.
+///
+/// `Box<T, DynAlloc>` and `RawVec<T, DynAlloc>` are compiled once, whatever
+/// the allocator actually used, and dispatch allocator calls dynamically.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::{Box, DynAlloc, Global, RawVec};
+///
+/// fn make_box(a: DynAlloc) -> Box<u32, DynAlloc> {
+///     Box::new_in(42, a)
+/// }
+///
+/// let mut a = Global;
+/// assert_eq!(*make_box(DynAlloc::new(&mut a)), 42);
+///
+/// let mut v: RawVec<u32, DynAlloc> = RawVec::new_in(DynAlloc::new(&mut a));
+/// v.reserve(0, 16);
+/// assert!(v.capacity() >= 16);
+/// # }
+/// ```
+pub struct DynAlloc<'a>(&'a mut dyn AllocRef);
+
+impl<'a> DynAlloc<'a> {
+    /// Erases the type of the given allocator.
+    pub fn new<A: AllocRef>(a: &'a mut A) -> Self {
+        DynAlloc(a)
+    }
+}
+
+impl<'a> From<&'a mut dyn AllocRef> for DynAlloc<'a> {
+    fn from(a: &'a mut dyn AllocRef) -> Self {
+        DynAlloc(a)
+    }
+}
+
+impl fmt::Debug for DynAlloc<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("DynAlloc")
+    }
+}
+
+unsafe impl AllocRef for DynAlloc<'_> {
+    forward_alloc_ref!(0);
+}
+
+/// An owned allocator whose type has been erased.
+///
+/// This is the owning counterpart of [`DynAlloc`], keeping the allocator on
+/// the heap. Each `BoxedAlloc` owns its own allocator, so sharing one
+/// allocator between several containers requires boxing a handle to it,
+/// e.g. an `Arc` or a shared reference.
+///
+/// [`DynAlloc`]: struct.DynAlloc.html
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::{Box, BoxedAlloc, Global};
+///
+/// let b: Box<u32, BoxedAlloc> = Box::new_in(42, BoxedAlloc::new(Global));
+/// assert_eq!(*b, 42);
+/// # }
+/// ```
+#[cfg(feature = "std")]
+pub struct BoxedAlloc(Box<dyn AllocRef + Send>);
+
+#[cfg(feature = "std")]
+impl BoxedAlloc {
+    /// Moves the given allocator to the heap and erases its type.
+    pub fn new<A: AllocRef + Send + 'static>(a: A) -> Self {
+        // `Box` can't be coerced to `Box<dyn AllocRef>` on stable, but raw
+        // pointers can.
+        let raw: *mut A = Box::into_raw(Box::new_in(a, Global));
+        let raw: *mut (dyn AllocRef + Send) = raw;
+        BoxedAlloc(unsafe { Box::from_raw_in(raw, Global) })
+    }
+
+    /// Borrows the allocator as a `DynAlloc`.
+    pub fn as_dyn(&mut self) -> DynAlloc<'_> {
+        DynAlloc(&mut *self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for BoxedAlloc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("BoxedAlloc")
+    }
+}
+
+#[cfg(feature = "std")]
+unsafe impl AllocRef for BoxedAlloc {
+    forward_alloc_ref!(0);
+}
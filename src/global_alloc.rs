@@ -0,0 +1,155 @@
+//! Adapter exposing an `AllocRef` through the `GlobalAlloc` interface.
+
+use core::fmt;
+use core::ptr::{self, NonNull};
+
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, GlobalAlloc, Layout, SharedAllocRef};
+use crate::sync::SpinLocked;
+
+/// A wrapper that allows an `AllocRef` to be used as a `GlobalAlloc`, and
+/// thus registered with `#[global_allocator]`.
+///
+/// `GlobalAlloc` methods take `&self` while `AllocRef` methods take
+/// `&mut self`, so every call goes through a [`SpinLocked`] protecting the
+/// wrapped allocator. Allocation failures, reported as `AllocErr` by
+/// `AllocRef`, are reported as null pointers, as `GlobalAlloc` expects.
+///
+/// Reallocations are forwarded to `AllocRef::realloc`, so allocators
+/// implementing `grow_in_place` or `shrink_in_place` keep the benefit of
+/// in-place reallocation.
+///
+/// [`SpinLocked`]: crate::sync::SpinLocked
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::{AllocErr, AllocRef, Layout, LockedGlobal};
+/// use std::alloc::{GlobalAlloc, System};
+/// use std::ptr::NonNull;
+///
+/// struct MyAlloc;
+///
+/// unsafe impl AllocRef for MyAlloc {
+///     fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+///         NonNull::new(unsafe { System.alloc(layout) })
+///             .ok_or(AllocErr)
+///             .map(|p| (p, layout.size()))
+///     }
+///
+///     unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+///         System.dealloc(ptr.as_ptr(), layout)
+///     }
+/// }
+///
+/// #[global_allocator]
+/// static GLOBAL: LockedGlobal<MyAlloc> = LockedGlobal::new(MyAlloc);
+///
+/// fn main() {
+///     let v = vec![1, 2, 3];
+///     assert_eq!(v.iter().sum::<i32>(), 6);
+/// }
+/// # }
+/// ```
+pub struct LockedGlobal<A> {
+    a: SpinLocked<A>,
+}
+
+impl<A> LockedGlobal<A> {
+    /// Wraps the given allocator.
+    pub const fn new(a: A) -> Self {
+        LockedGlobal { a: SpinLocked::new(a) }
+    }
+
+    /// Consumes the wrapper, returning the wrapped allocator.
+    pub fn into_inner(self) -> A {
+        self.a.into_inner()
+    }
+
+    /// Returns a mutable reference to the wrapped allocator.
+    ///
+    /// Since this call borrows the wrapper mutably, no locking needs to
+    /// take place.
+    pub fn get_mut(&mut self) -> &mut A {
+        self.a.get_mut()
+    }
+}
+
+impl<A: Default> Default for LockedGlobal<A> {
+    fn default() -> Self {
+        LockedGlobal::new(A::default())
+    }
+}
+
+impl<A> fmt::Debug for LockedGlobal<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LockedGlobal").field("a", &self.a).finish()
+    }
+}
+
+unsafe impl<A: AllocRef> GlobalAlloc for LockedGlobal<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.a.alloc(layout)
+            .map_or(ptr::null_mut(), |(p, _)| p.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.a.dealloc(NonNull::new_unchecked(ptr), layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.a.alloc_zeroed(layout)
+            .map_or(ptr::null_mut(), |(p, _)| p.as_ptr())
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.a.realloc(NonNull::new_unchecked(ptr), layout, new_size)
+            .map_or(ptr::null_mut(), |(p, _)| p.as_ptr())
+    }
+}
+
+unsafe impl<A: AllocRef> SharedAllocRef for LockedGlobal<A> {
+    fn alloc(&self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.a.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.a.dealloc(ptr, layout)
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        self.a.usable_size(layout)
+    }
+
+    fn alloc_zeroed(&self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.a.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.a.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn grow_in_place(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.a.grow_in_place(ptr, layout, new_size)
+    }
+
+    unsafe fn shrink_in_place(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.a.shrink_in_place(ptr, layout, new_size)
+    }
+}
@@ -0,0 +1,202 @@
+//! Helpers to compute the layout of objects made of several parts.
+//!
+//! [`LayoutBuilder`] computes the layout of a `#[repr(C)]`-like record one
+//! field at a time, and [`HeaderSlice`] describes the common case of a
+//! header followed by a variable number of elements, as used by
+//! reference-counted boxes or strings storing their length inline.
+//!
+//! [`LayoutBuilder`]: struct.LayoutBuilder.html
+//! [`HeaderSlice`]: struct.HeaderSlice.html
+
+use core::fmt;
+use core::marker::PhantomData;
+use core::ptr::{self, NonNull};
+
+use crate::alloc::{AllocErr, AllocRef, Layout, LayoutErr, LayoutExt};
+
+/// A builder computing the layout of a record from the layouts of its
+/// fields.
+///
+/// Fields are laid out in the order they are added, each at the first
+/// offset suitably aligned for it, like in a `#[repr(C)]` struct.
+///
+/// # Examples
+///
+/// ```
+/// use allocator_api::layout::LayoutBuilder;
+///
+/// let mut builder = LayoutBuilder::new();
+/// assert_eq!(builder.field::<u8>().unwrap(), 0);
+/// assert_eq!(builder.field::<u32>().unwrap(), 4);
+/// assert_eq!(builder.array::<u16>(3).unwrap(), 8);
+/// let layout = builder.finish();
+/// assert_eq!((layout.size(), layout.align()), (16, 4));
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LayoutBuilder {
+    layout: Layout,
+}
+
+impl Default for LayoutBuilder {
+    fn default() -> Self {
+        LayoutBuilder::new()
+    }
+}
+
+impl LayoutBuilder {
+    /// Creates a builder for a record without fields.
+    pub fn new() -> Self {
+        LayoutBuilder { layout: Layout::new::<()>() }
+    }
+
+    /// Adds a field with the given layout, and returns its offset.
+    ///
+    /// On arithmetic overflow, returns `LayoutErr`, and leaves the builder
+    /// unchanged.
+    pub fn field_layout(&mut self, layout: Layout) -> Result<usize, LayoutErr> {
+        let (new_layout, offset) = LayoutExt::extend(&self.layout, layout)?;
+        self.layout = new_layout;
+        Ok(offset)
+    }
+
+    /// Adds a field of type `T`, and returns its offset.
+    ///
+    /// On arithmetic overflow, returns `LayoutErr`, and leaves the builder
+    /// unchanged.
+    pub fn field<T>(&mut self) -> Result<usize, LayoutErr> {
+        self.field_layout(Layout::new::<T>())
+    }
+
+    /// Adds a field of type `[T; n]`, and returns its offset.
+    ///
+    /// On arithmetic overflow, returns `LayoutErr`, and leaves the builder
+    /// unchanged.
+    pub fn array<T>(&mut self, n: usize) -> Result<usize, LayoutErr> {
+        self.field_layout(<Layout as LayoutExt>::array::<T>(n)?)
+    }
+
+    /// Returns the layout of the record, padded to its alignment.
+    pub fn finish(self) -> Layout {
+        LayoutExt::pad_to_align(&self.layout)
+    }
+}
+
+/// The layout of an object made of a header of type `H` followed by a
+/// number of elements of type `T`.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::layout::HeaderSlice;
+/// use allocator_api::Global;
+///
+/// // A string storing its length inline.
+/// let s = "hello";
+/// let layout = HeaderSlice::<usize, u8>::new(s.len()).unwrap();
+/// let (header, bytes) = layout.alloc_in(&mut Global).unwrap();
+/// unsafe {
+///     header.as_ptr().write(s.len());
+///     bytes.cast::<u8>().as_ptr().copy_from_nonoverlapping(s.as_ptr(), s.len());
+///     assert_eq!(bytes.as_ref(), b"hello");
+///     layout.dealloc_in(&mut Global, header);
+/// }
+/// # }
+/// ```
+pub struct HeaderSlice<H, T> {
+    layout: Layout,
+    offset: usize,
+    len: usize,
+    marker: PhantomData<(*const H, *const T)>,
+}
+
+impl<H, T> HeaderSlice<H, T> {
+    /// Computes the layout of a header followed by `len` elements.
+    ///
+    /// On arithmetic overflow, returns `LayoutErr`.
+    pub fn new(len: usize) -> Result<Self, LayoutErr> {
+        let mut builder = LayoutBuilder::new();
+        builder.field::<H>()?;
+        let offset = builder.array::<T>(len)?;
+        Ok(HeaderSlice { layout: builder.finish(), offset, len, marker: PhantomData })
+    }
+
+    /// Returns the layout of the whole object.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Returns the offset of the first element.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the number of elements.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if there are no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns pointers to the header and the elements of the object
+    /// starting at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a block of at least `self.layout().size()`
+    /// bytes.
+    pub unsafe fn parts(&self, ptr: NonNull<u8>) -> (NonNull<H>, NonNull<[T]>) {
+        let elements = ptr.as_ptr().add(self.offset).cast::<T>();
+        let slice = ptr::slice_from_raw_parts_mut(elements, self.len);
+        (ptr.cast(), NonNull::new_unchecked(slice))
+    }
+
+    /// Allocates an object with `a`, and returns pointers to its
+    /// uninitialized header and elements.
+    ///
+    /// The object must be deallocated with [`dealloc_in`].
+    ///
+    /// [`dealloc_in`]: #method.dealloc_in
+    pub fn alloc_in<A: AllocRef>(&self, a: &mut A) -> Result<(NonNull<H>, NonNull<[T]>), AllocErr> {
+        let (ptr, _) = a.alloc(self.layout)?;
+        Ok(unsafe { self.parts(ptr) })
+    }
+
+    /// Deallocates an object allocated with [`alloc_in`]. The header and
+    /// elements are not dropped.
+    ///
+    /// # Safety
+    ///
+    /// `header` must have been returned by `alloc_in` with the same
+    /// allocator and layout.
+    ///
+    /// [`alloc_in`]: #method.alloc_in
+    pub unsafe fn dealloc_in<A: AllocRef>(&self, a: &mut A, header: NonNull<H>) {
+        a.dealloc(header.cast(), self.layout)
+    }
+}
+
+impl<H, T> Clone for HeaderSlice<H, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<H, T> Copy for HeaderSlice<H, T> {}
+
+impl<H, T> fmt::Debug for HeaderSlice<H, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeaderSlice")
+            .field("layout", &self.layout)
+            .field("offset", &self.offset)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests;
@@ -0,0 +1,67 @@
+use super::*;
+use crate::alloc::Global;
+use core::mem;
+
+#[test]
+fn builder_matches_repr_c() {
+    #[repr(C)]
+    struct Record {
+        a: u8,
+        b: u64,
+        c: u16,
+        d: [u32; 3],
+    }
+
+    let mut builder = LayoutBuilder::new();
+    let offsets = [
+        builder.field::<u8>().unwrap(),
+        builder.field::<u64>().unwrap(),
+        builder.field::<u16>().unwrap(),
+        builder.array::<u32>(3).unwrap(),
+    ];
+    let record = mem::MaybeUninit::<Record>::uninit();
+    let base = record.as_ptr() as usize;
+    let expected = unsafe {
+        let r = record.as_ptr();
+        [
+            core::ptr::addr_of!((*r).a) as usize - base,
+            core::ptr::addr_of!((*r).b) as usize - base,
+            core::ptr::addr_of!((*r).c) as usize - base,
+            core::ptr::addr_of!((*r).d) as usize - base,
+        ]
+    };
+    assert_eq!(offsets, expected);
+    assert_eq!(builder.finish(), Layout::new::<Record>());
+    assert_eq!(LayoutBuilder::new().finish(), Layout::new::<()>());
+
+    // Overflowing fields are rejected without changing the builder.
+    let before = builder;
+    assert!(builder.array::<u64>(usize::MAX / 4).is_err());
+    assert!(builder.field_layout(Layout::from_size_align(isize::MAX as usize, 1).unwrap()).is_err());
+    assert_eq!(builder, before);
+}
+
+#[test]
+fn header_slice() {
+    let layout = HeaderSlice::<(u8, u16), u64>::new(5).unwrap();
+    assert_eq!(layout.offset(), 8);
+    assert_eq!(layout.len(), 5);
+    assert_eq!(layout.layout(), Layout::from_size_align(48, 8).unwrap());
+
+    let (header, slice) = layout.alloc_in(&mut Global).unwrap();
+    unsafe {
+        assert_eq!(slice.cast::<u8>().as_ptr() as usize - header.as_ptr() as usize, 8);
+        header.as_ptr().write((1, 2));
+        for (i, x) in (*slice.as_ptr()).iter_mut().enumerate() {
+            *x = i as u64;
+        }
+        assert_eq!(*header.as_ptr(), (1, 2));
+        assert_eq!(slice.as_ref(), &[0, 1, 2, 3, 4]);
+        layout.dealloc_in(&mut Global, header);
+    }
+
+    let empty = HeaderSlice::<u32, u8>::new(0).unwrap();
+    assert!(empty.is_empty());
+    assert_eq!(empty.layout(), Layout::new::<u32>());
+    assert!(HeaderSlice::<u8, u32>::new(usize::MAX / 2).is_err());
+}
@@ -45,20 +45,38 @@ pub mod collections;
 #[path = "liballoc/raw_vec.rs"]
 pub mod raw_vec;
 
+pub mod bitmap;
+pub mod buddy;
+#[cfg(feature = "std")]
+pub mod cache;
+pub mod combinators;
+pub mod debug;
+mod dyn_alloc;
+mod global_alloc;
+pub mod layout;
+pub mod limit;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub mod os;
+pub mod stats;
+pub mod sync;
+pub mod testing;
+pub mod tlsf;
+pub mod trace;
+
 #[cfg(feature = "std")]
 extern crate std;
 
 #[cfg(feature = "std")]
 mod global {
     use core::ptr::NonNull;
-    use crate::core_alloc::{AllocErr, Layout};
+    use crate::core_alloc::{AllocErr, AllocRef, Layout};
 
     use std::alloc::{alloc, alloc_zeroed, dealloc, realloc};
 
     #[derive(Copy, Clone, Default, Debug)]
     pub struct Global;
 
-    unsafe impl crate::core_alloc::AllocRef for Global {
+    unsafe impl AllocRef for Global {
         fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
             NonNull::new(unsafe { alloc(layout.into()) })
                 .ok_or(AllocErr)
@@ -86,12 +104,43 @@ mod global {
                 .map(|p| (p, layout.size()))
         }
     }
+
+    unsafe impl crate::core_alloc::SharedAllocRef for Global {
+        #[inline]
+        fn alloc(&self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+            AllocRef::alloc(&mut Global, layout)
+        }
+
+        #[inline]
+        unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+            AllocRef::dealloc(&mut Global, ptr, layout)
+        }
+
+        #[inline]
+        unsafe fn realloc(
+            &self,
+            ptr: NonNull<u8>,
+            layout: Layout,
+            new_size: usize,
+        ) -> Result<(NonNull<u8>, usize), AllocErr> {
+            AllocRef::realloc(&mut Global, ptr, layout, new_size)
+        }
+
+        #[inline]
+        fn alloc_zeroed(&self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+            AllocRef::alloc_zeroed(&mut Global, layout)
+        }
+    }
 }
 
 pub mod alloc {
     pub use crate::core_alloc::*;
     pub use crate::std_alloc::rust_oom as handle_alloc_error;
     pub use crate::std_alloc::{set_alloc_error_hook, take_alloc_error_hook};
+    pub use crate::dyn_alloc::DynAlloc;
+    #[cfg(feature = "std")]
+    pub use crate::dyn_alloc::BoxedAlloc;
+    pub use crate::global_alloc::LockedGlobal;
 
     #[cfg(feature = "std")]
     pub use crate::global::Global;
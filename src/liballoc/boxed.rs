@@ -21,7 +21,7 @@ use core::task::{Context, Poll};
 
 #[cfg(feature = "std")]
 use crate::alloc::Global;
-use crate::alloc::{handle_alloc_error, AllocRef, Layout};
+use crate::alloc::{handle_alloc_error, AllocErr, AllocRef, CannotReallocInPlace, Layout};
 use crate::raw_vec::RawVec;
 use crate::Unique;
 
@@ -366,6 +366,79 @@ impl<T: ?Sized, A: AllocRef> Drop for Box<T, A> {
     }
 }
 
+/// A boxed allocator is itself an allocator, forwarding to the boxed value.
+unsafe impl<T: AllocRef + ?Sized, A: AllocRef> AllocRef for Box<T, A> {
+    #[inline]
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        (**self).alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        (**self).dealloc(ptr, layout)
+    }
+
+    #[inline]
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        (**self).usable_size(layout)
+    }
+
+    #[inline]
+    fn alloc_zeroed(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        (**self).alloc_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        (**self).realloc(ptr, layout, new_size)
+    }
+
+    #[inline]
+    unsafe fn realloc_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        (**self).realloc_zeroed(ptr, layout, new_size)
+    }
+
+    #[inline]
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        (**self).grow_in_place(ptr, layout, new_size)
+    }
+
+    #[inline]
+    unsafe fn grow_in_place_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        (**self).grow_in_place_zeroed(ptr, layout, new_size)
+    }
+
+    #[inline]
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        (**self).shrink_in_place(ptr, layout, new_size)
+    }
+}
+
 impl<T: Default, A: AllocRef + Default> Default for Box<T, A> {
     /// Creates a `Box<T>`, with the `Default` value for T.
     fn default() -> Box<T, A> {
@@ -455,7 +528,7 @@ impl<A: AllocRef + Clone> Clone for Box<str, A> {
         let buf = RawVec::with_capacity_in(len, self.1.clone());
         unsafe {
             ptr::copy_nonoverlapping(self.as_ptr(), buf.ptr(), len);
-            from_boxed_utf8_unchecked(buf.into_box())
+            from_boxed_utf8_unchecked(buf.into_box_with_len(len))
         }
     }
 }
@@ -609,7 +682,7 @@ impl<T: Copy, A: AllocRef + Default> From<&[T]> for Box<[T], A> {
         let buf = RawVec::with_capacity_in(len, a);
         unsafe {
             ptr::copy_nonoverlapping(slice.as_ptr(), buf.ptr(), len);
-            buf.into_box()
+            buf.into_box_with_len(len)
         }
     }
 }
@@ -777,8 +850,9 @@ impl<T: Clone, A: AllocRef + Clone> Clone for Box<[T], A> {
         impl<T, A: AllocRef> BoxBuilder<T, A> {
             unsafe fn into_box(self) -> Box<[T], A> {
                 let raw = ptr::read(&self.data);
+                let len = self.len;
                 mem::forget(self);
-                raw.into_box()
+                raw.into_box_with_len(len)
             }
         }
 
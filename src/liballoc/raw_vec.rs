@@ -6,7 +6,7 @@ use core::slice;
 
 #[cfg(feature = "std")]
 use crate::alloc::Global;
-use crate::alloc::{handle_alloc_error, AllocErr, AllocRef, Layout, LayoutExt};
+use crate::alloc::{handle_alloc_error, AllocErr, AllocRef, Excess, Layout, LayoutExt};
 use crate::boxed::Box;
 use crate::collections::TryReserveError::{self, *};
 use crate::Unique;
@@ -87,9 +87,13 @@ impl<T, A: AllocRef> RawVec<T, A> {
         } else {
             let align = mem::align_of::<T>();
             let layout = Layout::from_size_align(alloc_size, align).unwrap();
-            let result = if zeroed { a.alloc_zeroed(layout) } else { a.alloc(layout) };
+            let result = if zeroed {
+                a.alloc_zeroed(layout).map(|(ptr, size)| Excess(ptr, size))
+            } else {
+                a.alloc_excess(layout)
+            };
             match result {
-                Ok((ptr, size)) => {
+                Ok(Excess(ptr, size)) => {
                     capacity = size / elem_size;
                     ptr.cast()
                 }
@@ -305,9 +309,10 @@ impl<T, A: AllocRef> RawVec<T, A> {
                     let new_cap = 2 * self.cap;
                     let new_size = new_cap * elem_size;
                     alloc_guard(new_size).unwrap_or_else(|_| capacity_overflow());
-                    let ptr_res = self.a.realloc(NonNull::from(self.ptr).cast(), cur, new_size);
+                    let ptr_res =
+                        self.a.realloc_excess(NonNull::from(self.ptr).cast(), cur, new_size);
                     match ptr_res {
-                        Ok((ptr, new_size)) => (ptr, new_size / elem_size),
+                        Ok(Excess(ptr, new_size)) => (ptr, new_size / elem_size),
                         Err(_) => handle_alloc_error(Layout::from_size_align_unchecked(
                             new_size,
                             cur.align(),
@@ -319,8 +324,8 @@ impl<T, A: AllocRef> RawVec<T, A> {
                     // would cause overflow.
                     let new_cap = if elem_size > (!0) / 8 { 1 } else { 4 };
                     let layout = <Layout as LayoutExt>::array::<T>(new_cap).unwrap();
-                    match self.a.alloc(layout) {
-                        Ok((ptr, new_size)) => (ptr, new_size / elem_size),
+                    match self.a.alloc_excess(layout) {
+                        Ok(Excess(ptr, new_size)) => (ptr, new_size / elem_size),
                         Err(_) => handle_alloc_error(layout),
                     }
                 }
@@ -672,9 +677,9 @@ impl<T, A: AllocRef> RawVec<T, A> {
             let res = match self.current_layout() {
                 Some(layout) => {
                     debug_assert!(new_layout.align() == layout.align());
-                    self.a.realloc(NonNull::from(self.ptr).cast(), layout, new_layout.size())
+                    self.a.realloc_excess(NonNull::from(self.ptr).cast(), layout, new_layout.size())
                 }
-                None => self.a.alloc(new_layout),
+                None => self.a.alloc_excess(new_layout),
             };
 
             let (ptr, new_cap) = match (res, fallibility) {
@@ -685,7 +690,7 @@ impl<T, A: AllocRef> RawVec<T, A> {
                         non_exhaustive: (),
                     });
                 }
-                (Ok((ptr, new_size)), _) => (ptr, new_size / elem_size),
+                (Ok(Excess(ptr, new_size)), _) => (ptr, new_size / elem_size),
             };
 
             self.ptr = ptr.cast().into();
@@ -715,6 +720,27 @@ impl<T, A: AllocRef> RawVec<T, A> {
         mem::forget(self);
         output
     }
+
+    /// Converts the first `len` elements of the buffer into `Box<[T], A>`,
+    /// without reallocating.
+    ///
+    /// The allocator may have returned more capacity than requested. A
+    /// layout for `len` elements still fits the block as long as `len` is
+    /// at least the requested capacity, so the excess is simply left unused
+    /// by the box.
+    ///
+    /// # Undefined Behavior
+    ///
+    /// The first `len` elements must be initialized, and `len` must lie
+    /// between the capacity requested from the allocator and `capacity()`.
+    pub(crate) unsafe fn into_box_with_len(self, len: usize) -> Box<[T], A> {
+        debug_assert!(len <= self.capacity());
+        let slice = slice::from_raw_parts_mut(self.ptr(), len);
+        let a = ptr::read(&self.a);
+        let output: Box<[T], A> = Box::from_raw_in(slice, a);
+        mem::forget(self);
+        output
+    }
 }
 
 impl<T, A: AllocRef> RawVec<T, A> {
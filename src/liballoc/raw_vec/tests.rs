@@ -76,3 +76,107 @@ fn reserve_does_not_overallocate() {
         assert!(v.capacity() >= 12 + 12 / 2);
     }
 }
+
+#[test]
+fn allocator_by_reference() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut a = Global;
+    {
+        let mut v: RawVec<u8, _> = RawVec::with_capacity_in(50, &mut a);
+        v.reserve(50, 150);
+        assert!(v.capacity() >= 200);
+        let b = Box::new_in(42, v.alloc_mut());
+        assert_eq!(*b, 42);
+    }
+
+    let shared = RefCell::new(a);
+    {
+        let v: RawVec<u32, _> = RawVec::with_capacity_in(10, &shared);
+        let w: RawVec<u32, _> = RawVec::with_capacity_in(10, &shared);
+        assert_ne!(v.ptr(), w.ptr());
+    }
+
+    let rc = Rc::new(shared);
+    let v: RawVec<u64, _> = RawVec::with_capacity_in(10, rc.clone());
+    let b = Box::new_in(1, rc.clone());
+    assert_eq!(Rc::strong_count(&rc), 3);
+    drop((v, b));
+    assert_eq!(Rc::strong_count(&rc), 1);
+}
+
+#[test]
+fn excess_capacity() {
+    use crate::alloc::{AllocErr, Excess};
+
+    // An allocator rounding sizes up to a multiple of 64 bytes.
+    #[derive(Clone, Default)]
+    struct Rounding;
+
+    fn rounded(layout: Layout) -> Layout {
+        Layout::from_size_align((layout.size() + 63) & !63, layout.align()).unwrap()
+    }
+
+    unsafe impl AllocRef for Rounding {
+        fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+            Global.alloc(rounded(layout))
+        }
+        unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+            Global.dealloc(ptr, rounded(layout))
+        }
+        fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+            (layout.size(), rounded(*layout).size())
+        }
+    }
+
+    let layout = Layout::from_size_align(10, 2).unwrap();
+    assert_eq!(Rounding.usable_size(&layout), (10, 64));
+    let mut a = Rounding;
+    let by_ref = &mut a;
+    assert_eq!(by_ref.usable_size(&layout), (10, 64));
+    let Excess(ptr, size) = Rounding.alloc_excess(layout).unwrap();
+    assert_eq!(size, 64);
+    let Excess(ptr, size) = unsafe { Rounding.realloc_excess(ptr, layout, 100) }.unwrap();
+    assert_eq!(size, 128);
+    unsafe { Rounding.dealloc(ptr, Layout::from_size_align(100, 2).unwrap()) };
+
+    // `RawVec` uses the whole block.
+    let mut v: RawVec<u16, _> = RawVec::with_capacity_in(5, Rounding);
+    assert_eq!(v.capacity(), 32);
+    v.reserve_exact(32, 1);
+    assert_eq!(v.capacity(), 64);
+    v.reserve(64, 1);
+    assert_eq!(v.capacity(), 128);
+
+    // Boxed slices keep their length, and can be deallocated with it.
+    let b: Box<[u16], Rounding> = Box::from(&[1, 2, 3][..]);
+    assert_eq!(&*b, &[1, 2, 3]);
+    let c = b.clone();
+    assert_eq!(&*c, &[1, 2, 3]);
+    let s: Box<str, Rounding> = Box::from("hello");
+    assert_eq!(&*s.clone(), "hello");
+
+    // Wrappers report the usable sizes of the allocator they wrap, and
+    // `Quota` charges blocks for them.
+    use crate::limit::{Budget, Quota};
+    use crate::stats::{Counting, Stats};
+    use crate::sync::SpinLocked;
+
+    let locked = SpinLocked::new(Rounding);
+    assert_eq!((&locked).usable_size(&layout), (10, 64));
+    let budget = Budget::new(1000);
+    let stats = Stats::new();
+    let a = Counting::new(Quota::new(Rounding, &budget), &stats);
+    assert_eq!(a.usable_size(&layout), (10, 64));
+    let mut v: RawVec<u16, _> = RawVec::with_capacity_in(5, a);
+    assert_eq!(v.capacity(), 32);
+    assert_eq!(budget.remaining(), 1000 - 64);
+    v.reserve(32, 1);
+    assert_eq!(v.capacity(), 64);
+    assert_eq!(budget.remaining(), 1000 - 128);
+    assert_eq!(stats.snapshot().live_bytes, 128);
+    drop(v);
+    assert_eq!(budget.remaining(), 1000);
+    assert_eq!(stats.snapshot().live_bytes, 0);
+}
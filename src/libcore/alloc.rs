@@ -1,5 +1,6 @@
 //! Memory allocation APIs
 
+use core::cell::RefCell;
 use core::cmp;
 use core::fmt;
 use core::mem;
@@ -8,17 +9,63 @@ use core::usize;
 
 /// Represents the combination of a starting address and
 /// a total capacity of the returned block.
-#[derive(Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Excess(pub NonNull<u8>, pub usize);
 
-pub use core::alloc::{Layout, LayoutErr};
+pub use core::alloc::{GlobalAlloc, Layout, LayoutErr};
 
-pub(crate) trait LayoutExt: Sized {
+/// Extension methods for `Layout`, providing the layout arithmetic that is
+/// not stable in `core`.
+///
+/// The methods follow the semantics of their nightly counterparts. Some of
+/// them have since been stabilized as inherent methods of `Layout`, which
+/// take precedence in method call syntax, so call them through the trait
+/// to get the behavior documented here.
+///
+/// # Examples
+///
+/// ```
+/// use allocator_api::alloc::{Layout, LayoutExt};
+///
+/// // The layout of a `#[repr(C)]` struct holding a `u8` and a `[u32; 3]`.
+/// let array = <Layout as LayoutExt>::array::<u32>(3).unwrap();
+/// let (layout, offset) = LayoutExt::extend(&Layout::new::<u8>(), array).unwrap();
+/// assert_eq!(offset, 4);
+/// assert_eq!(LayoutExt::pad_to_align(&layout), Layout::from_size_align(16, 4).unwrap());
+/// ```
+pub trait LayoutExt: Sized {
+    /// Returns the amount of padding that must be inserted after `self` so
+    /// that the following address satisfies `align`.
     fn padding_needed_for(&self, align: usize) -> usize;
 
+    /// Creates a layout with the same alignment, and the size rounded up to
+    /// a multiple of the alignment.
+    fn pad_to_align(&self) -> Self;
+
+    /// Creates a layout describing `n` instances of `self`, each padded to
+    /// the alignment, and returns it along with the distance between the
+    /// starts of consecutive instances.
     fn repeat(&self, n: usize) -> Result<(Self, usize), LayoutErr>;
 
+    /// Creates a layout describing `n` instances of `self`, without padding
+    /// between them.
+    fn repeat_packed(&self, n: usize) -> Result<Self, LayoutErr>;
+
+    /// Creates a layout describing `self` followed by `next`, with the
+    /// padding needed to align `next`, and returns it along with the offset
+    /// of `next`. The resulting layout is not padded at the end.
+    fn extend(&self, next: Self) -> Result<(Self, usize), LayoutErr>;
+
+    /// Creates a layout describing `self` followed by `next`, without
+    /// padding. The alignment of `next` is ignored.
+    fn extend_packed(&self, next: Self) -> Result<Self, LayoutErr>;
+
+    /// Creates a layout describing a `[T; n]`.
     fn array<T>(n: usize) -> Result<Self, LayoutErr>;
+
+    /// Creates a layout with the same size, and an alignment of at least
+    /// `align`.
+    fn align_to(&self, align: usize) -> Result<Self, LayoutErr>;
 }
 
 fn layouterr() -> LayoutErr {
@@ -28,8 +75,6 @@ fn layouterr() -> LayoutErr {
     Layout::from_size_align(0, 0).err().unwrap()
 }
 
-/// Implements the unstable parts of the core::alloc::Layout type that
-/// this crate uses.
 impl LayoutExt for Layout {
     /// Returns the amount of padding we must insert after `self`
     /// to ensure that the following address will satisfy `align`
@@ -91,15 +136,12 @@ impl LayoutExt for Layout {
         let padded_size = self.size() + LayoutExt::padding_needed_for(self, self.align());
         let alloc_size = padded_size.checked_mul(n).ok_or_else(layouterr)?;
 
-        unsafe {
-            // self.align is already known to be valid and alloc_size has been
-            // padded already.
-            Ok((
-                Layout::from_size_align_unchecked(alloc_size, self.align()),
-                padded_size,
-            ))
-        }
+        // alloc_size has been padded already, but may still be too large
+        // for a layout.
+        let layout = Layout::from_size_align(alloc_size, self.align())?;
+        Ok((layout, padded_size))
     }
+
     /// Creates a layout describing the record for a `[T; n]`.
     ///
     /// On arithmetic overflow, returns `LayoutErr`.
@@ -110,8 +152,80 @@ impl LayoutExt for Layout {
             k
         })
     }
+
+    /// Creates a layout by rounding the size of this layout up to a
+    /// multiple of the layout's alignment.
+    ///
+    /// This can't overflow, because of the invariant of `Layout`.
+    #[inline]
+    fn pad_to_align(&self) -> Self {
+        let new_size = self.size() + LayoutExt::padding_needed_for(self, self.align());
+        unsafe { Layout::from_size_align_unchecked(new_size, self.align()) }
+    }
+
+    /// Creates a layout describing the record for `n` instances of
+    /// `self`, with no padding between each instance.
+    ///
+    /// On arithmetic overflow, returns `LayoutErr`.
+    #[inline]
+    fn repeat_packed(&self, n: usize) -> Result<Self, LayoutErr> {
+        let size = self.size().checked_mul(n).ok_or_else(layouterr)?;
+        Layout::from_size_align(size, self.align())
+    }
+
+    /// Creates a layout describing the record for `self` followed by
+    /// `next`, including any necessary padding to ensure that `next`
+    /// will be properly aligned. Note that the resulting layout will
+    /// satisfy the alignment properties of both `self` and `next`, but
+    /// is not padded to its alignment at the end.
+    ///
+    /// On arithmetic overflow, returns `LayoutErr`.
+    #[inline]
+    fn extend(&self, next: Self) -> Result<(Self, usize), LayoutErr> {
+        let new_align = cmp::max(self.align(), next.align());
+        let pad = LayoutExt::padding_needed_for(self, next.align());
+
+        let offset = self.size().checked_add(pad).ok_or_else(layouterr)?;
+        let new_size = offset.checked_add(next.size()).ok_or_else(layouterr)?;
+
+        let layout = Layout::from_size_align(new_size, new_align)?;
+        Ok((layout, offset))
+    }
+
+    /// Creates a layout describing the record for `self` followed by
+    /// `next` with no additional padding between the two. Since no
+    /// padding is inserted, the alignment of `next` is irrelevant, and
+    /// is not incorporated into the resulting layout.
+    ///
+    /// On arithmetic overflow, returns `LayoutErr`.
+    #[inline]
+    fn extend_packed(&self, next: Self) -> Result<Self, LayoutErr> {
+        let new_size = self.size().checked_add(next.size()).ok_or_else(layouterr)?;
+        Layout::from_size_align(new_size, self.align())
+    }
+
+    /// Creates a layout describing the record that can hold a value
+    /// of the same layout as `self`, but that also is aligned to
+    /// alignment `align` (measured in bytes).
+    ///
+    /// If `self` already meets the prescribed alignment, then returns
+    /// `self`.
+    ///
+    /// Returns an error if `align` is not a power of two, or if the
+    /// rounded up size would overflow.
+    #[inline]
+    fn align_to(&self, align: usize) -> Result<Self, LayoutErr> {
+        if !align.is_power_of_two() {
+            return Err(layouterr());
+        }
+        Layout::from_size_align(self.size(), cmp::max(self.align(), align))
+    }
 }
 
+#[cfg(all(test, feature = "std"))]
+#[path = "alloc/tests.rs"]
+mod tests;
+
 /// The `AllocErr` error indicates an allocation failure
 /// that may be due to resource exhaustion or to
 /// something wrong when combining the given input arguments with this
@@ -273,6 +387,22 @@ pub unsafe trait AllocRef {
     ///   to allocate that block of memory.
     unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout);
 
+    /// Returns bounds on the size of the block returned for a successful
+    /// allocation with `layout`, as `(min, max)`.
+    ///
+    /// A block allocated with `layout` has a size in `[min, max]`, so any
+    /// size in that range *fits* it as long as it is at least
+    /// `layout.size()`. In particular, `max` is the capacity a client can
+    /// count on before allocating, for instance to pick a request size
+    /// that wastes no memory.
+    ///
+    /// The default implementation returns `(layout.size(), layout.size())`,
+    /// which is correct for any allocator, but may understate `max` for
+    /// allocators rounding sizes up.
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        (layout.size(), layout.size())
+    }
+
     /// Behaves like `alloc`, but also ensures that the contents
     /// are set to zero before being returned.
     ///
@@ -437,6 +567,37 @@ pub unsafe trait AllocRef {
         result
     }
 
+    /// Behaves like `alloc`, but returns the whole usable size of the
+    /// block as an `Excess`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` in the same cases as `alloc`.
+    #[inline]
+    fn alloc_excess(&mut self, layout: Layout) -> Result<Excess, AllocErr> {
+        self.alloc(layout).map(|(ptr, size)| Excess(ptr, size))
+    }
+
+    /// Behaves like `realloc`, but returns the whole usable size of the
+    /// block as an `Excess`.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe for the same reasons that `realloc` is.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` in the same cases as `realloc`.
+    #[inline]
+    unsafe fn realloc_excess(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<Excess, AllocErr> {
+        self.realloc(ptr, layout, new_size).map(|(ptr, size)| Excess(ptr, size))
+    }
+
     /// Attempts to extend the allocation referenced by `ptr` to fit `new_size`.
     ///
     /// If this returns `Ok`, then the allocator has asserted that the
@@ -566,3 +727,473 @@ pub unsafe trait AllocRef {
         Err(CannotReallocInPlace)
     }
 }
+
+unsafe impl<A: AllocRef + ?Sized> AllocRef for &mut A {
+    #[inline]
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        (**self).alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        (**self).dealloc(ptr, layout)
+    }
+
+    #[inline]
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        (**self).usable_size(layout)
+    }
+
+    #[inline]
+    fn alloc_zeroed(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        (**self).alloc_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        (**self).realloc(ptr, layout, new_size)
+    }
+
+    #[inline]
+    unsafe fn realloc_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        (**self).realloc_zeroed(ptr, layout, new_size)
+    }
+
+    #[inline]
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        (**self).grow_in_place(ptr, layout, new_size)
+    }
+
+    #[inline]
+    unsafe fn grow_in_place_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        (**self).grow_in_place_zeroed(ptr, layout, new_size)
+    }
+
+    #[inline]
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        (**self).shrink_in_place(ptr, layout, new_size)
+    }
+}
+
+/// The error type for the array methods of [`AllocRefExt`].
+///
+/// [`AllocRefExt`]: trait.AllocRefExt.html
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ArrayAllocErr {
+    /// The size of the array overflows.
+    Layout(LayoutErr),
+    /// The allocator returned an error.
+    Alloc(AllocErr),
+}
+
+impl From<LayoutErr> for ArrayAllocErr {
+    #[inline]
+    fn from(e: LayoutErr) -> Self {
+        ArrayAllocErr::Layout(e)
+    }
+}
+
+impl From<AllocErr> for ArrayAllocErr {
+    #[inline]
+    fn from(e: AllocErr) -> Self {
+        ArrayAllocErr::Alloc(e)
+    }
+}
+
+impl fmt::Display for ArrayAllocErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArrayAllocErr::Layout(e) => fmt::Display::fmt(e, f),
+            ArrayAllocErr::Alloc(e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+/// Typed allocation methods, implemented for every [`AllocRef`].
+///
+/// Layouts are computed from the type, and zero-sized requests (of
+/// zero-sized types, or of empty arrays) don't reach the allocator:
+/// they are given dangling pointers, which are ignored on deallocation.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::alloc::{AllocRefExt, ArrayAllocErr, Global};
+///
+/// let mut a = Global;
+/// let ptr = a.alloc_array::<u32>(4).unwrap();
+/// unsafe {
+///     ptr.as_ptr().write_bytes(0, 4);
+///     let ptr = a.realloc_array(ptr, 4, 8).unwrap();
+///     assert_eq!(*ptr.as_ptr().add(3), 0);
+///     a.dealloc_array(ptr, 8).unwrap();
+/// }
+/// match a.alloc_array::<u32>(usize::MAX) {
+///     Err(ArrayAllocErr::Layout(_)) => {}
+///     _ => panic!(),
+/// }
+/// # }
+/// ```
+///
+/// [`AllocRef`]: trait.AllocRef.html
+pub trait AllocRefExt: AllocRef {
+    /// Allocates memory suitable for holding an instance of `T`.
+    ///
+    /// The returned memory is uninitialized.
+    fn alloc_one<T>(&mut self) -> Result<NonNull<T>, AllocErr> {
+        let layout = Layout::new::<T>();
+        if layout.size() == 0 {
+            return Ok(NonNull::dangling());
+        }
+        self.alloc(layout).map(|(ptr, _)| ptr.cast())
+    }
+
+    /// Deallocates memory allocated with `alloc_one::<T>`. The instance
+    /// is not dropped.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `alloc_one::<T>` on this
+    /// allocator, and not deallocated since.
+    unsafe fn dealloc_one<T>(&mut self, ptr: NonNull<T>) {
+        let layout = Layout::new::<T>();
+        if layout.size() != 0 {
+            self.dealloc(ptr.cast(), layout);
+        }
+    }
+
+    /// Allocates memory suitable for holding `n` instances of `T`.
+    ///
+    /// The returned memory is uninitialized.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ArrayAllocErr::Layout` if the size of the array overflows,
+    /// and `ArrayAllocErr::Alloc` if the allocator fails.
+    fn alloc_array<T>(&mut self, n: usize) -> Result<NonNull<T>, ArrayAllocErr> {
+        let layout = <Layout as LayoutExt>::array::<T>(n)?;
+        if layout.size() == 0 {
+            return Ok(NonNull::dangling());
+        }
+        let (ptr, _) = self.alloc(layout)?;
+        Ok(ptr.cast())
+    }
+
+    /// Resizes an array allocated with `alloc_array::<T>` from `n_old` to
+    /// `n_new` instances, preserving the first `min(n_old, n_new)` of
+    /// them.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `alloc_array::<T>(n_old)` or
+    /// `realloc_array::<T>(_, _, n_old)` on this allocator, and not
+    /// deallocated since.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ArrayAllocErr::Layout` if the size of the new array
+    /// overflows, and `ArrayAllocErr::Alloc` if the allocator fails. In
+    /// both cases, the array is left unchanged.
+    unsafe fn realloc_array<T>(
+        &mut self,
+        ptr: NonNull<T>,
+        n_old: usize,
+        n_new: usize,
+    ) -> Result<NonNull<T>, ArrayAllocErr> {
+        let old = <Layout as LayoutExt>::array::<T>(n_old)?;
+        let new = <Layout as LayoutExt>::array::<T>(n_new)?;
+        if old.size() == 0 {
+            return self.alloc_array(n_new);
+        }
+        if new.size() == 0 {
+            self.dealloc(ptr.cast(), old);
+            return Ok(NonNull::dangling());
+        }
+        let (ptr, _) = self.realloc(ptr.cast(), old, new.size())?;
+        Ok(ptr.cast())
+    }
+
+    /// Deallocates an array allocated with `alloc_array::<T>(n)`. The
+    /// instances are not dropped.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `alloc_array::<T>(n)` or
+    /// `realloc_array::<T>(_, _, n)` on this allocator, and not
+    /// deallocated since.
+    ///
+    /// # Errors
+    ///
+    /// Returns `LayoutErr` if the size of the array overflows, in which
+    /// case it cannot have been allocated with this `n`.
+    unsafe fn dealloc_array<T>(&mut self, ptr: NonNull<T>, n: usize) -> Result<(), LayoutErr> {
+        let layout = <Layout as LayoutExt>::array::<T>(n)?;
+        if layout.size() != 0 {
+            self.dealloc(ptr.cast(), layout);
+        }
+        Ok(())
+    }
+}
+
+impl<A: AllocRef + ?Sized> AllocRefExt for A {}
+
+/// An allocator that can be used through a shared reference.
+///
+/// The methods of this trait mirror those of [`AllocRef`], with the same
+/// contracts, but take `&self` instead of `&mut self`. Implementors are
+/// expected to handle synchronization themselves (or to be stateless).
+///
+/// Every `&A` where `A: SharedAllocRef` implements `AllocRef`, which allows
+/// a single allocator to back several `Box` or `RawVec` instances.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::{Box, Global};
+/// use std::cell::RefCell;
+///
+/// let a = RefCell::new(Global);
+/// let x = Box::new_in(5, &a);
+/// let y = Box::new_in(6, &a);
+/// assert_eq!(*x + *y, 11);
+/// # }
+/// ```
+///
+/// # Safety
+///
+/// Implementors must uphold the same contracts as for [`AllocRef`].
+///
+/// [`AllocRef`]: trait.AllocRef.html
+pub unsafe trait SharedAllocRef {
+    /// Behaves like `AllocRef::alloc`.
+    fn alloc(&self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr>;
+
+    /// Behaves like `AllocRef::dealloc`.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe for the same reasons that `AllocRef::dealloc` is.
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout);
+
+    /// Behaves like `AllocRef::usable_size`.
+    #[inline]
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        (layout.size(), layout.size())
+    }
+
+    /// Behaves like `AllocRef::alloc_zeroed`.
+    fn alloc_zeroed(&self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let size = layout.size();
+        let result = self.alloc(layout);
+        if let Ok((p, _)) = result {
+            unsafe { ptr::write_bytes(p.as_ptr(), 0, size) }
+        }
+        result
+    }
+
+    /// Behaves like `AllocRef::realloc`.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe for the same reasons that `AllocRef::realloc` is.
+    unsafe fn realloc(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let old_size = layout.size();
+
+        if new_size > old_size {
+            if let Ok(size) = self.grow_in_place(ptr, layout, new_size) {
+                return Ok((ptr, size));
+            }
+        } else if new_size < old_size {
+            if let Ok(size) = self.shrink_in_place(ptr, layout, new_size) {
+                return Ok((ptr, size));
+            }
+        } else {
+            return Ok((ptr, new_size));
+        }
+
+        // otherwise, fall back on alloc + copy + dealloc.
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let result = self.alloc(new_layout);
+        if let Ok((new_ptr, _)) = result {
+            ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), cmp::min(old_size, new_size));
+            self.dealloc(ptr, layout);
+        }
+        result
+    }
+
+    /// Behaves like `AllocRef::grow_in_place`.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe for the same reasons that `AllocRef::grow_in_place` is.
+    #[inline]
+    unsafe fn grow_in_place(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let _ = ptr;
+        let _ = layout;
+        let _ = new_size;
+        Err(CannotReallocInPlace)
+    }
+
+    /// Behaves like `AllocRef::shrink_in_place`.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe for the same reasons that `AllocRef::shrink_in_place` is.
+    #[inline]
+    unsafe fn shrink_in_place(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let _ = ptr;
+        let _ = layout;
+        let _ = new_size;
+        Err(CannotReallocInPlace)
+    }
+}
+
+unsafe impl<A: SharedAllocRef + ?Sized> AllocRef for &A {
+    #[inline]
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        SharedAllocRef::alloc(*self, layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        SharedAllocRef::dealloc(*self, ptr, layout)
+    }
+
+    #[inline]
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        SharedAllocRef::usable_size(*self, layout)
+    }
+
+    #[inline]
+    fn alloc_zeroed(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        SharedAllocRef::alloc_zeroed(*self, layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        SharedAllocRef::realloc(*self, ptr, layout, new_size)
+    }
+
+    #[inline]
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        SharedAllocRef::grow_in_place(*self, ptr, layout, new_size)
+    }
+
+    #[inline]
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        SharedAllocRef::shrink_in_place(*self, ptr, layout, new_size)
+    }
+}
+
+/// A `RefCell` around an allocator can be shared within a thread. Reentrant
+/// uses of the allocator (e.g. from within its own methods) panic.
+unsafe impl<A: AllocRef + ?Sized> SharedAllocRef for RefCell<A> {
+    #[inline]
+    fn alloc(&self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.borrow_mut().alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.borrow_mut().dealloc(ptr, layout)
+    }
+
+    #[inline]
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        self.borrow().usable_size(layout)
+    }
+
+    #[inline]
+    fn alloc_zeroed(&self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.borrow_mut().alloc_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.borrow_mut().realloc(ptr, layout, new_size)
+    }
+
+    #[inline]
+    unsafe fn grow_in_place(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.borrow_mut().grow_in_place(ptr, layout, new_size)
+    }
+
+    #[inline]
+    unsafe fn shrink_in_place(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.borrow_mut().shrink_in_place(ptr, layout, new_size)
+    }
+}
@@ -0,0 +1,131 @@
+use super::*;
+
+fn layout(size: usize, align: usize) -> Layout {
+    Layout::from_size_align(size, align).unwrap()
+}
+
+/// The largest size a layout with the given alignment can have.
+fn max_size(align: usize) -> usize {
+    isize::MAX as usize - (align - 1)
+}
+
+#[test]
+fn layout_padding() {
+    assert_eq!(LayoutExt::padding_needed_for(&layout(9, 4), 4), 3);
+    assert_eq!(LayoutExt::padding_needed_for(&layout(8, 4), 4), 0);
+    assert_eq!(LayoutExt::padding_needed_for(&layout(1, 1), 16), 15);
+    assert_eq!(LayoutExt::pad_to_align(&layout(9, 4)), layout(12, 4));
+    assert_eq!(LayoutExt::pad_to_align(&layout(0, 8)), layout(0, 8));
+    assert_eq!(LayoutExt::pad_to_align(&layout(max_size(8), 8)), layout(max_size(8), 8));
+}
+
+#[test]
+fn layout_repeat() {
+    assert_eq!(LayoutExt::repeat(&layout(9, 4), 3).unwrap(), (layout(36, 4), 12));
+    assert_eq!(LayoutExt::repeat(&layout(9, 4), 0).unwrap(), (layout(0, 4), 12));
+    assert_eq!(LayoutExt::repeat(&layout(0, 4), usize::MAX).unwrap(), (layout(0, 4), 0));
+    assert!(LayoutExt::repeat(&layout(9, 4), usize::MAX / 12 + 1).is_err());
+    assert!(LayoutExt::repeat(&layout(max_size(2), 2), 2).is_err());
+
+    assert_eq!(LayoutExt::repeat_packed(&layout(9, 4), 3).unwrap(), layout(27, 4));
+    assert_eq!(LayoutExt::repeat_packed(&layout(0, 4), usize::MAX).unwrap(), layout(0, 4));
+    assert!(LayoutExt::repeat_packed(&layout(2, 1), usize::MAX / 2 + 1).is_err());
+    // The product fits, but rounding it up to the alignment overflows.
+    assert!(LayoutExt::repeat_packed(&layout(1, 2), usize::MAX).is_err());
+}
+
+#[test]
+fn layout_array() {
+    assert_eq!(<Layout as LayoutExt>::array::<u32>(3).unwrap(), layout(12, 4));
+    assert_eq!(<Layout as LayoutExt>::array::<()>(usize::MAX).unwrap(), layout(0, 1));
+    assert_eq!(<Layout as LayoutExt>::array::<[u8; 3]>(5).unwrap(), layout(15, 1));
+    assert!(<Layout as LayoutExt>::array::<u64>(usize::MAX / 8 + 1).is_err());
+}
+
+#[test]
+fn layout_extend() {
+    assert_eq!(LayoutExt::extend(&layout(1, 1), layout(8, 4)).unwrap(), (layout(12, 4), 4));
+    assert_eq!(LayoutExt::extend(&layout(8, 8), layout(1, 1)).unwrap(), (layout(9, 8), 8));
+    assert_eq!(LayoutExt::extend(&layout(0, 1), layout(0, 16)).unwrap(), (layout(0, 16), 0));
+    // Padding the first layout overflows.
+    assert!(LayoutExt::extend(&layout(max_size(1), 1), layout(0, 2)).is_err());
+    // Adding the second layout overflows.
+    assert!(LayoutExt::extend(&layout(max_size(1), 1), layout(1, 1)).is_err());
+    // The sum fits, but rounding it up to the alignment overflows.
+    assert!(LayoutExt::extend(&layout(max_size(8), 1), layout(1, 8)).is_err());
+
+    assert_eq!(LayoutExt::extend_packed(&layout(1, 1), layout(8, 4)).unwrap(), layout(9, 1));
+    assert_eq!(LayoutExt::extend_packed(&layout(1, 8), layout(1, 1)).unwrap(), layout(2, 8));
+    assert!(LayoutExt::extend_packed(&layout(max_size(1), 1), layout(1, 1)).is_err());
+    assert!(LayoutExt::extend_packed(&layout(max_size(8), 8), layout(1, 1)).is_err());
+}
+
+#[test]
+fn layout_align_to() {
+    assert_eq!(LayoutExt::align_to(&layout(9, 4), 16).unwrap(), layout(9, 16));
+    assert_eq!(LayoutExt::align_to(&layout(9, 16), 4).unwrap(), layout(9, 16));
+    assert!(LayoutExt::align_to(&layout(9, 4), 3).is_err());
+    assert!(LayoutExt::align_to(&layout(max_size(1), 1), 2).is_err());
+}
+
+#[test]
+fn typed_helpers() {
+    use crate::alloc::Global;
+    use crate::stats::{Counting, Stats};
+
+    let stats = Stats::new();
+    let mut a = Counting::new(Global, &stats);
+    unsafe {
+        let one = a.alloc_one::<u64>().unwrap();
+        one.as_ptr().write(42);
+        a.dealloc_one(one);
+
+        let array = a.alloc_array::<u16>(3).unwrap();
+        array.as_ptr().copy_from_nonoverlapping([1, 2, 3].as_ptr(), 3);
+        let array = a.realloc_array(array, 3, 100).unwrap();
+        assert_eq!(*array.as_ptr().add(2), 3);
+        let array = a.realloc_array(array, 100, 0).unwrap();
+        assert_eq!(array, NonNull::dangling());
+        let array = a.realloc_array(array, 0, 2).unwrap();
+        a.dealloc_array(array, 2).unwrap();
+    }
+    let snapshot = stats.snapshot();
+    assert_eq!((snapshot.allocs, snapshot.reallocs, snapshot.deallocs), (3, 1, 3));
+    assert_eq!(snapshot.live_bytes, 0);
+
+    // Zero-sized requests don't reach the allocator.
+    unsafe {
+        let unit = a.alloc_one::<()>().unwrap();
+        a.dealloc_one(unit);
+        let units = a.alloc_array::<()>(usize::MAX).unwrap();
+        let units = a.realloc_array(units, usize::MAX, 7).unwrap();
+        a.dealloc_array(units, 7).unwrap();
+        let empty = a.alloc_array::<u64>(0).unwrap();
+        assert_eq!(empty, NonNull::dangling());
+        a.dealloc_array(empty, 0).unwrap();
+    }
+    assert_eq!(stats.snapshot(), snapshot);
+}
+
+#[test]
+fn typed_helpers_errors() {
+    use crate::alloc::Global;
+    use crate::testing::{FailingAlloc, Faults};
+
+    let faults = Faults::nth(1);
+    let mut a = FailingAlloc::new(Global, &faults);
+    match a.alloc_array::<u64>(usize::MAX / 4) {
+        Err(ArrayAllocErr::Layout(_)) => {}
+        r => panic!("{:?}", r),
+    }
+    let array = a.alloc_array::<u64>(4).unwrap();
+    unsafe {
+        match a.realloc_array(array, 4, usize::MAX / 4) {
+            Err(ArrayAllocErr::Layout(_)) => {}
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(a.realloc_array(array, 4, 8), Err(ArrayAllocErr::Alloc(AllocErr)));
+        assert!(a.dealloc_array(array, usize::MAX / 4).is_err());
+        a.dealloc_array(array, 4).unwrap();
+    }
+}
@@ -54,3 +54,71 @@ pub fn rust_oom(layout: Layout) -> ! {
     hook(layout);
     loop {}
 }
+
+#[cfg(feature = "std")]
+mod shared {
+    use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout, SharedAllocRef};
+    use core::ptr::NonNull;
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    macro_rules! shared_alloc_ref {
+        ($($t:ident),*) => { $(
+            /// Reference-counted handles to a shared allocator can be cloned
+            /// freely, all clones allocating from the same allocator.
+            unsafe impl<A: SharedAllocRef + ?Sized> AllocRef for $t<A> {
+                #[inline]
+                fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+                    SharedAllocRef::alloc(&**self, layout)
+                }
+
+                #[inline]
+                unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+                    SharedAllocRef::dealloc(&**self, ptr, layout)
+                }
+
+                #[inline]
+                fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+                    SharedAllocRef::usable_size(&**self, layout)
+                }
+
+                #[inline]
+                fn alloc_zeroed(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+                    SharedAllocRef::alloc_zeroed(&**self, layout)
+                }
+
+                #[inline]
+                unsafe fn realloc(
+                    &mut self,
+                    ptr: NonNull<u8>,
+                    layout: Layout,
+                    new_size: usize,
+                ) -> Result<(NonNull<u8>, usize), AllocErr> {
+                    SharedAllocRef::realloc(&**self, ptr, layout, new_size)
+                }
+
+                #[inline]
+                unsafe fn grow_in_place(
+                    &mut self,
+                    ptr: NonNull<u8>,
+                    layout: Layout,
+                    new_size: usize,
+                ) -> Result<usize, CannotReallocInPlace> {
+                    SharedAllocRef::grow_in_place(&**self, ptr, layout, new_size)
+                }
+
+                #[inline]
+                unsafe fn shrink_in_place(
+                    &mut self,
+                    ptr: NonNull<u8>,
+                    layout: Layout,
+                    new_size: usize,
+                ) -> Result<usize, CannotReallocInPlace> {
+                    SharedAllocRef::shrink_in_place(&**self, ptr, layout, new_size)
+                }
+            }
+        )* };
+    }
+
+    shared_alloc_ref!(Rc, Arc);
+}
@@ -0,0 +1,331 @@
+//! Allocation limits.
+//!
+//! [`Quota`] wraps any `AllocRef` and makes allocations fail with `AllocErr`
+//! once they would exceed the limit set by its [`Policy`]. The provided
+//! [`Budget`] policy caps the number of bytes allocated at any given time,
+//! and can be shared between several `Quota` allocators.
+//!
+//! [`Quota`]: struct.Quota.html
+//! [`Policy`]: trait.Policy.html
+//! [`Budget`]: struct.Budget.html
+
+use core::borrow::Borrow;
+use core::cmp;
+use core::fmt;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout};
+use crate::combinators::Owns;
+
+/// A policy deciding whether allocations may proceed.
+///
+/// Sizes given to the policy are the largest usable sizes the wrapped
+/// allocator reports for the layouts given by callers (see
+/// `AllocRef::usable_size`), so that blocks are charged the same whichever
+/// of their usable sizes they are deallocated with.
+pub trait Policy {
+    /// Attempts to charge `size` bytes against the limit. Returns `false` if
+    /// the allocation must be refused, in which case nothing is charged.
+    fn charge(&self, size: usize) -> bool;
+
+    /// Gives back `size` bytes previously charged.
+    fn refund(&self, size: usize);
+}
+
+impl<P: Policy + ?Sized> Policy for &P {
+    #[inline]
+    fn charge(&self, size: usize) -> bool {
+        (**self).charge(size)
+    }
+
+    #[inline]
+    fn refund(&self, size: usize) {
+        (**self).refund(size)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<P: Policy + ?Sized> Policy for std::sync::Arc<P> {
+    #[inline]
+    fn charge(&self, size: usize) -> bool {
+        (**self).charge(size)
+    }
+
+    #[inline]
+    fn refund(&self, size: usize) {
+        (**self).refund(size)
+    }
+}
+
+/// A policy limiting the number of bytes allocated at any given time.
+///
+/// The remaining budget is kept in an atomic counter, so a `Budget` can be
+/// shared between `Quota` allocators, even across threads, by reference
+/// (or through an `Arc`).
+pub struct Budget {
+    limit: usize,
+    remaining: AtomicUsize,
+}
+
+impl Budget {
+    /// Creates a budget allowing up to `limit` bytes to be allocated.
+    pub const fn new(limit: usize) -> Self {
+        Budget { limit, remaining: AtomicUsize::new(limit) }
+    }
+
+    /// Returns the number of bytes the budget was created with.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Returns the number of bytes that can still be allocated.
+    pub fn remaining(&self) -> usize {
+        self.remaining.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes currently charged against the budget.
+    pub fn used(&self) -> usize {
+        self.limit - self.remaining()
+    }
+}
+
+impl Policy for Budget {
+    fn charge(&self, size: usize) -> bool {
+        self.remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| {
+                remaining.checked_sub(size)
+            })
+            .is_ok()
+    }
+
+    fn refund(&self, size: usize) {
+        self.remaining.fetch_add(size, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Budget")
+            .field("limit", &self.limit)
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
+
+/// An allocator wrapper enforcing a limit [`Policy`].
+///
+/// Allocations are charged against the policy before reaching the wrapped
+/// allocator, and refunded when deallocated, or when the wrapped allocator
+/// fails. Growing and shrinking reallocations, in place or not, are charged
+/// or refunded the size difference. The sizes returned to callers are capped
+/// to the charged sizes.
+///
+/// [`Policy`]: trait.Policy.html
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::collections::TryReserveError;
+/// use allocator_api::limit::{Budget, Quota};
+/// use allocator_api::{Box, Global, RawVec};
+///
+/// let budget = Budget::new(100);
+/// let mut v: RawVec<u8, _> = RawVec::with_capacity_in(50, Quota::new(Global, &budget));
+/// let b = Box::new_in([0u8; 30], Quota::new(Global, &budget));
+/// assert_eq!(budget.remaining(), 20);
+///
+/// match v.try_reserve_exact(50, 50) {
+///     Err(TryReserveError::AllocError { .. }) => {}
+///     _ => panic!("reservation should have failed"),
+/// }
+///
+/// drop(b);
+/// assert_eq!(budget.remaining(), 50);
+/// assert!(v.try_reserve_exact(50, 50).is_ok());
+/// assert_eq!(budget.remaining(), 0);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Quota<A, P> {
+    a: A,
+    policy: P,
+}
+
+impl<A: AllocRef, P: Policy> Quota<A, P> {
+    /// Wraps the given allocator, enforcing the given policy.
+    pub fn new(a: A, policy: P) -> Self {
+        Quota { a, policy }
+    }
+
+    /// Returns a reference to the policy.
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    /// Returns a reference to the wrapped allocator.
+    pub fn get_ref(&self) -> &A {
+        &self.a
+    }
+
+    /// Consumes the wrapper, returning the wrapped allocator.
+    pub fn into_inner(self) -> A {
+        self.a
+    }
+
+    fn charged<T, E>(
+        &mut self,
+        size: usize,
+        err: E,
+        f: impl FnOnce(&mut A) -> Result<T, E>,
+    ) -> Result<T, E> {
+        if !self.policy.charge(size) {
+            return Err(err);
+        }
+        let result = f(&mut self.a);
+        if result.is_err() {
+            self.policy.refund(size);
+        }
+        result
+    }
+
+    /// Returns the size charged for blocks of the given size and alignment.
+    fn cost(&self, size: usize, align: usize) -> usize {
+        let layout = unsafe { Layout::from_size_align_unchecked(size, align) };
+        self.a.usable_size(&layout).1
+    }
+
+    /// Charges or refunds the difference between the costs of `layout` and
+    /// of `new_size` around `f`.
+    fn resized<T, E>(
+        &mut self,
+        layout: Layout,
+        new_size: usize,
+        err: E,
+        f: impl FnOnce(&mut A) -> Result<T, E>,
+    ) -> Result<(T, usize), E> {
+        let old_cost = self.cost(layout.size(), layout.align());
+        let new_cost = self.cost(new_size, layout.align());
+        let result = if new_cost > old_cost {
+            self.charged(new_cost - old_cost, err, f)
+        } else {
+            self.refunded(old_cost - new_cost, f)
+        };
+        result.map(|t| (t, new_cost))
+    }
+
+    fn refunded<T, E>(
+        &mut self,
+        size: usize,
+        f: impl FnOnce(&mut A) -> Result<T, E>,
+    ) -> Result<T, E> {
+        let result = f(&mut self.a);
+        if result.is_ok() {
+            self.policy.refund(size);
+        }
+        result
+    }
+}
+
+/// `remaining` is available whether the `Budget` is owned, borrowed, or
+/// shared through an `Arc`.
+impl<A: AllocRef, P: Policy + Borrow<Budget>> Quota<A, P> {
+    /// Returns the number of bytes that can still be allocated.
+    pub fn remaining(&self) -> usize {
+        self.policy.borrow().remaining()
+    }
+}
+
+unsafe impl<A: AllocRef, P: Policy> AllocRef for Quota<A, P> {
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let cost = self.cost(layout.size(), layout.align());
+        let (ptr, size) = self.charged(cost, AllocErr, |a| a.alloc(layout))?;
+        Ok((ptr, cmp::min(size, cost)))
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let cost = self.cost(layout.size(), layout.align());
+        self.a.dealloc(ptr, layout);
+        self.policy.refund(cost);
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        self.a.usable_size(layout)
+    }
+
+    fn alloc_zeroed(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let cost = self.cost(layout.size(), layout.align());
+        let (ptr, size) = self.charged(cost, AllocErr, |a| a.alloc_zeroed(layout))?;
+        Ok((ptr, cmp::min(size, cost)))
+    }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let ((ptr, size), cost) =
+            self.resized(layout, new_size, AllocErr, |a| a.realloc(ptr, layout, new_size))?;
+        Ok((ptr, cmp::min(size, cost)))
+    }
+
+    unsafe fn realloc_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let ((ptr, size), cost) = self.resized(layout, new_size, AllocErr, |a| {
+            a.realloc_zeroed(ptr, layout, new_size)
+        })?;
+        Ok((ptr, cmp::min(size, cost)))
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let (size, cost) = self.resized(layout, new_size, CannotReallocInPlace, |a| {
+            a.grow_in_place(ptr, layout, new_size)
+        })?;
+        Ok(cmp::min(size, cost))
+    }
+
+    unsafe fn grow_in_place_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let (size, cost) = self.resized(layout, new_size, CannotReallocInPlace, |a| {
+            a.grow_in_place_zeroed(ptr, layout, new_size)
+        })?;
+        Ok(cmp::min(size, cost))
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let (size, cost) = self.resized(layout, new_size, CannotReallocInPlace, |a| {
+            a.shrink_in_place(ptr, layout, new_size)
+        })?;
+        Ok(cmp::min(size, cost))
+    }
+}
+
+impl<A: Owns, P> Owns for Quota<A, P> {
+    fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        self.a.owns(ptr, layout)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests;
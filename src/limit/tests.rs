@@ -0,0 +1,87 @@
+use super::*;
+use crate::alloc::Global;
+use crate::raw_vec::RawVec;
+
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[test]
+fn quota_rejects_over_budget() {
+    let mut a = Quota::new(Global, Budget::new(100));
+    let (p, _) = a.alloc(layout(60)).unwrap();
+    assert_eq!(a.alloc(layout(41)), Err(AllocErr));
+    assert_eq!(a.alloc_zeroed(layout(41)), Err(AllocErr));
+    // Rejected requests aren't charged.
+    assert_eq!(a.remaining(), 40);
+    let (q, _) = a.alloc(layout(40)).unwrap();
+    assert_eq!(a.remaining(), 0);
+    assert_eq!(a.alloc(layout(0)).map(|_| ()), Ok(()));
+    unsafe {
+        assert_eq!(a.grow_in_place(q, layout(40), 48), Err(CannotReallocInPlace));
+        assert_eq!(a.realloc(q, layout(40), 48), Err(AllocErr));
+        a.dealloc(p, layout(60));
+        a.dealloc(q, layout(40));
+    }
+    assert_eq!(a.remaining(), 100);
+}
+
+#[test]
+fn quota_refunds_on_dealloc_and_realloc() {
+    let budget = Arc::new(Budget::new(1000));
+    let mut a = Quota::new(Global, budget.clone());
+    unsafe {
+        let (p, _) = a.alloc(layout(100)).unwrap();
+        assert_eq!(a.remaining(), 900);
+        let (p, _) = a.realloc(p, layout(100), 300).unwrap();
+        assert_eq!(a.remaining(), 700);
+        let (p, _) = a.realloc(p, layout(300), 50).unwrap();
+        assert_eq!(a.remaining(), 950);
+        a.dealloc(p, layout(50));
+    }
+    assert_eq!(budget.remaining(), 1000);
+
+    let mut v: RawVec<u32, _> = RawVec::with_capacity_in(10, Quota::new(Global, &*budget));
+    v.reserve(10, 90);
+    assert_eq!(budget.used(), v.capacity() * 4);
+    v.shrink_to_fit(20);
+    assert_eq!(budget.used(), 80);
+    drop(v);
+    assert_eq!(budget.used(), 0);
+}
+
+#[test]
+fn quota_charges_concurrently() {
+    const THREADS: usize = 8;
+    const LIMIT: usize = 64 * 1024;
+
+    let budget = Arc::new(Budget::new(LIMIT));
+    let barrier = Arc::new(Barrier::new(THREADS));
+    let handles: std::vec::Vec<_> = (0..THREADS)
+        .map(|_| {
+            let mut a = Quota::new(Global, budget.clone());
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                let mut blocks = std::vec::Vec::new();
+                // Allocate until the shared budget runs out.
+                while let Ok((ptr, _)) = a.alloc(layout(64)) {
+                    blocks.push(ptr);
+                }
+                barrier.wait();
+                assert_eq!(a.remaining(), 0);
+                barrier.wait();
+                for &ptr in &blocks {
+                    unsafe { a.dealloc(ptr, layout(64)) };
+                }
+                blocks.len()
+            })
+        })
+        .collect();
+    let count: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+    // Exactly the budget was handed out, never more.
+    assert_eq!(count, LIMIT / 64);
+    assert_eq!(budget.remaining(), LIMIT);
+}
@@ -0,0 +1,229 @@
+use core::cmp;
+use core::ptr::{self, NonNull};
+
+use super::page_size;
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout};
+
+/// The size of transparent huge pages on common architectures.
+const HUGE_PAGE: usize = 2 << 20;
+
+/// An allocator mapping anonymous memory for each block.
+///
+/// Block sizes are rounded up to a multiple of the page size, and the
+/// rounded up size is returned as the usable size. Memory is given back to
+/// the system as soon as a block is deallocated, and resizing blocks
+/// doesn't copy their contents: `realloc` and `grow_in_place` remap them
+/// with `mremap`, the former moving the mapping if necessary, and
+/// `shrink_in_place` unmaps the pages past the new end.
+///
+/// Each block taking at least a page, and a system call to allocate, this
+/// is best used for large blocks, for instance behind a
+/// [`Segregate`](../combinators/struct.Segregate.html).
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::os::Mmap;
+/// use allocator_api::RawVec;
+///
+/// let mut v: RawVec<u8, _> = RawVec::with_capacity_in(10000, Mmap::new().huge_pages());
+/// assert_eq!(v.capacity() % 4096, 0);
+/// assert!(v.capacity() >= 10000);
+/// let cap = v.capacity();
+/// v.reserve(cap, 10 << 20);
+/// assert!(v.capacity() >= cap + (10 << 20));
+/// # }
+/// ```
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Mmap {
+    huge_pages: bool,
+}
+
+impl Mmap {
+    /// Creates an allocator.
+    pub const fn new() -> Self {
+        Mmap { huge_pages: false }
+    }
+
+    /// Advises the system to back blocks of at least 2 MiB with transparent
+    /// huge pages.
+    pub const fn huge_pages(mut self) -> Self {
+        self.huge_pages = true;
+        self
+    }
+
+    unsafe fn advise(&self, ptr: *mut libc::c_void, len: usize) {
+        if self.huge_pages && len >= HUGE_PAGE {
+            // This is only advice, failures don't matter.
+            libc::madvise(ptr, len, libc::MADV_HUGEPAGE);
+        }
+    }
+}
+
+/// Returns the length of the mapping holding a block of `size` bytes.
+fn mapping_len(size: usize, page: usize) -> Option<usize> {
+    Some(cmp::max(size.checked_add(page - 1)? & !(page - 1), page))
+}
+
+unsafe impl AllocRef for Mmap {
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let page = page_size();
+        let len = mapping_len(layout.size(), page).ok_or(AllocErr)?;
+        // Mappings are page aligned, larger alignments require mapping more
+        // and unmapping the excess.
+        let map_len = len
+            .checked_add(layout.align().saturating_sub(page))
+            .ok_or(AllocErr)?;
+        unsafe {
+            let base = libc::mmap(
+                ptr::null_mut(),
+                map_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if base == libc::MAP_FAILED {
+                return Err(AllocErr);
+            }
+            let base = base as usize;
+            let start = (base + layout.align() - 1) & !(layout.align() - 1);
+            if start > base {
+                libc::munmap(base as *mut libc::c_void, start - base);
+            }
+            if base + map_len > start + len {
+                libc::munmap((start + len) as *mut libc::c_void, base + map_len - start - len);
+            }
+            self.advise(start as *mut libc::c_void, len);
+            Ok((NonNull::new_unchecked(start as *mut u8), len))
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let len = mapping_len(layout.size(), page_size()).unwrap();
+        libc::munmap(ptr.as_ptr() as *mut libc::c_void, len);
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        let len = mapping_len(layout.size(), page_size()).unwrap_or(layout.size());
+        (layout.size(), len)
+    }
+
+    fn alloc_zeroed(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        // Fresh anonymous mappings are zeroed.
+        self.alloc(layout)
+    }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let page = page_size();
+        let old_len = mapping_len(layout.size(), page).unwrap();
+        let new_len = mapping_len(new_size, page).ok_or(AllocErr)?;
+        if new_len == old_len {
+            return Ok((ptr, old_len));
+        }
+        if layout.align() <= page {
+            let new_ptr = libc::mremap(
+                ptr.as_ptr() as *mut libc::c_void,
+                old_len,
+                new_len,
+                libc::MREMAP_MAYMOVE,
+            );
+            if new_ptr == libc::MAP_FAILED {
+                return Err(AllocErr);
+            }
+            if new_len > old_len {
+                self.advise(new_ptr, new_len);
+            }
+            return Ok((NonNull::new_unchecked(new_ptr as *mut u8), new_len));
+        }
+
+        // `mremap` may move the block to an address that is not aligned
+        // enough, so only remap in place.
+        if new_len < old_len {
+            if let Ok(size) = self.shrink_in_place(ptr, layout, new_size) {
+                return Ok((ptr, size));
+            }
+        } else if let Ok(size) = self.grow_in_place(ptr, layout, new_size) {
+            return Ok((ptr, size));
+        }
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let (new_ptr, size) = self.alloc(new_layout)?;
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), cmp::min(layout.size(), new_size));
+        self.dealloc(ptr, layout);
+        Ok((new_ptr, size))
+    }
+
+    unsafe fn realloc_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let old_len = mapping_len(layout.size(), page_size()).unwrap();
+        let (new_ptr, size) = self.realloc(ptr, layout, new_size)?;
+        // Only the pages that were already mapped may hold non-zero bytes.
+        if new_size > layout.size() {
+            let end = cmp::min(new_size, old_len);
+            new_ptr.as_ptr().add(layout.size()).write_bytes(0, end - layout.size());
+        }
+        Ok((new_ptr, size))
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let page = page_size();
+        let old_len = mapping_len(layout.size(), page).unwrap();
+        let new_len = mapping_len(new_size, page).ok_or(CannotReallocInPlace)?;
+        if new_len <= old_len {
+            return Ok(old_len);
+        }
+        let new_ptr = libc::mremap(ptr.as_ptr() as *mut libc::c_void, old_len, new_len, 0);
+        if new_ptr == libc::MAP_FAILED {
+            return Err(CannotReallocInPlace);
+        }
+        self.advise(new_ptr, new_len);
+        Ok(new_len)
+    }
+
+    unsafe fn grow_in_place_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let old_len = mapping_len(layout.size(), page_size()).unwrap();
+        let size = self.grow_in_place(ptr, layout, new_size)?;
+        // Only the pages that were already mapped may hold non-zero bytes.
+        let end = cmp::min(new_size, old_len);
+        ptr.as_ptr().add(layout.size()).write_bytes(0, end - layout.size());
+        Ok(size)
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let page = page_size();
+        let old_len = mapping_len(layout.size(), page).unwrap();
+        let new_len = mapping_len(new_size, page).unwrap();
+        if new_len < old_len
+            && libc::munmap(ptr.as_ptr().add(new_len) as *mut libc::c_void, old_len - new_len) != 0
+        {
+            return Err(CannotReallocInPlace);
+        }
+        Ok(new_len)
+    }
+}
@@ -0,0 +1,16 @@
+//! Allocators getting memory directly from the operating system.
+
+mod mmap;
+mod persistent;
+mod shm;
+
+pub use self::mmap::Mmap;
+pub use self::persistent::PersistentHeap;
+pub use self::shm::{OffsetBox, OffsetPtr, SharedHeap, SharedRegion};
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,501 @@
+use core::fmt;
+use core::mem;
+use core::ptr::{self, NonNull};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout};
+use crate::combinators::Owns;
+
+const MAGIC: [u8; 8] = *b"ALLOCPH1";
+
+/// The header at the start of the file. All the addresses in the file are
+/// offsets from its start, so that they stay valid wherever the file is
+/// mapped.
+#[repr(C)]
+struct FileHeader {
+    magic: [u8; 8],
+    size: u64,
+    /// Offset of the root object, or 0.
+    root: u64,
+    /// Offset of the first free block, or 0.
+    free: u64,
+    reserved: [u64; 4],
+}
+
+/// The header preceding each block.
+#[repr(C)]
+struct Block {
+    /// Size of the block, not counting the header.
+    size: u64,
+    state: u64,
+}
+
+const USED: u64 = u64::from_le_bytes(*b"usedblck");
+const FREE: u64 = u64::from_le_bytes(*b"freeblck");
+
+const DATA: usize = mem::size_of::<FileHeader>();
+const HEADER: usize = mem::size_of::<Block>();
+const ALIGN: usize = 16;
+/// The smallest block, large enough to hold the offset of the next free
+/// block.
+const MIN_SIZE: usize = 16;
+
+fn corrupt(what: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what)
+}
+
+/// Returns the block size for a request of `size` bytes.
+fn adjust(size: usize) -> Option<usize> {
+    let size = size.checked_add(ALIGN - 1)? & !(ALIGN - 1);
+    Some(if size < MIN_SIZE { MIN_SIZE } else { size })
+}
+
+/// An allocator managing a heap inside a memory-mapped file.
+///
+/// All the allocator's state is kept in the file: a header holds the list
+/// of free blocks, and each block is preceded by a header holding its size
+/// and state. Reopening the file recovers the blocks that were allocated,
+/// and the root object set with [`set_root`], from which applications can
+/// find their data again. Since the file may be mapped at a different
+/// address, blocks should refer to each other with offsets, see
+/// [`offset_of`] and [`at_offset`].
+///
+/// Free blocks are kept in a list ordered by address, so allocations take
+/// the first block that fits, and freed blocks are coalesced with their
+/// neighbours.
+///
+/// Changes reach the file through the page cache, [`flush`] waits until
+/// they are written. The consistency of the heap is checked when the file
+/// is opened.
+///
+/// [`set_root`]: #method.set_root
+/// [`offset_of`]: #method.offset_of
+/// [`at_offset`]: #method.at_offset
+/// [`flush`]: #method.flush
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::alloc::{AllocRef, Layout};
+/// use allocator_api::os::PersistentHeap;
+///
+/// let path = std::env::temp_dir().join(format!("persistent-heap-doc-{}", std::process::id()));
+/// {
+///     let mut heap = PersistentHeap::create(&path, 1 << 16).unwrap();
+///     let (ptr, _) = heap.alloc(Layout::new::<u64>()).unwrap();
+///     unsafe { ptr.cast::<u64>().as_ptr().write(42) };
+///     heap.set_root(Some(ptr));
+///     heap.flush().unwrap();
+/// }
+/// let heap = PersistentHeap::open(&path).unwrap();
+/// let root = heap.root().unwrap();
+/// assert_eq!(unsafe { *root.cast::<u64>().as_ptr() }, 42);
+/// # std::fs::remove_file(&path).unwrap();
+/// # }
+/// ```
+pub struct PersistentHeap {
+    file: File,
+    base: NonNull<u8>,
+    size: usize,
+}
+
+// The mapping is only accessed through raw pointers.
+unsafe impl Send for PersistentHeap {}
+
+impl PersistentHeap {
+    /// Creates a file of `size` bytes at `path`, replacing any existing
+    /// file, and formats it as an empty heap.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is too small to hold a block.
+    pub fn create<P: AsRef<Path>>(path: P, size: usize) -> io::Result<Self> {
+        let size = size & !(ALIGN - 1);
+        assert!(size >= DATA + HEADER + MIN_SIZE, "heap too small");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(size as u64)?;
+        let heap = Self::map(file, size)?;
+        unsafe {
+            heap.header().write(FileHeader {
+                magic: MAGIC,
+                size: size as u64,
+                root: 0,
+                free: DATA as u64,
+                reserved: [0; 4],
+            });
+            heap.block(DATA).write(Block {
+                size: (size - DATA - HEADER) as u64,
+                state: FREE,
+            });
+            heap.set_next_free(DATA, 0);
+        }
+        Ok(heap)
+    }
+
+    /// Opens a heap created with [`create`], and checks its consistency.
+    ///
+    /// Returns an error of kind `InvalidData` if the file is not a heap, or
+    /// if the heap is inconsistent.
+    ///
+    /// [`create`]: #method.create
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let size = file.metadata()?.len() as usize;
+        if size < DATA + HEADER + MIN_SIZE {
+            return Err(corrupt("file too small to be a heap"));
+        }
+        let heap = Self::map(file, size)?;
+        heap.check()?;
+        Ok(heap)
+    }
+
+    fn map(file: File, size: usize) -> io::Result<Self> {
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let base = unsafe { NonNull::new_unchecked(base as *mut u8) };
+        Ok(PersistentHeap { file, base, size })
+    }
+
+    /// Waits until all the changes to the heap are written to the file.
+    pub fn flush(&self) -> io::Result<()> {
+        let result = unsafe { libc::msync(self.base.as_ptr() as *mut libc::c_void, self.size, libc::MS_SYNC) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Returns the root object, if any.
+    pub fn root(&self) -> Option<NonNull<u8>> {
+        match unsafe { (*self.header()).root } {
+            0 => None,
+            root => Some(self.at_offset(root as usize)),
+        }
+    }
+
+    /// Sets the root object, which must be a block allocated from this
+    /// heap, or `None`.
+    ///
+    /// The root is reset to `None` when its block is deallocated.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `root` is not in the heap.
+    pub fn set_root(&mut self, root: Option<NonNull<u8>>) {
+        let offset = root.map_or(0, |root| self.offset_of(root));
+        unsafe { (*self.header()).root = offset as u64 };
+    }
+
+    /// Returns the offset of `ptr` from the start of the file.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ptr` is not in the heap.
+    pub fn offset_of(&self, ptr: NonNull<u8>) -> usize {
+        let offset = (ptr.as_ptr() as usize).wrapping_sub(self.base.as_ptr() as usize);
+        assert!(
+            offset >= DATA && offset < self.size,
+            "{:p} is not in the heap",
+            ptr
+        );
+        offset
+    }
+
+    /// Returns the address at `offset` from the start of the file.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is not in the heap.
+    pub fn at_offset(&self, offset: usize) -> NonNull<u8> {
+        assert!(
+            offset >= DATA && offset < self.size,
+            "offset {} is not in the heap",
+            offset
+        );
+        unsafe { NonNull::new_unchecked(self.base.as_ptr().add(offset)) }
+    }
+
+    /// Checks the consistency of the heap, walking all its blocks and its
+    /// list of free blocks.
+    ///
+    /// This is done when opening the file, and returns the same errors.
+    pub fn check(&self) -> io::Result<()> {
+        unsafe {
+            let header = &*self.header();
+            if header.magic != MAGIC {
+                return Err(corrupt("not a heap file"));
+            }
+            if header.size != self.size as u64 {
+                return Err(corrupt("heap size doesn't match the file size"));
+            }
+            // The free list is ordered by address, so it is walked along
+            // with the blocks.
+            let mut next_free = header.free as usize;
+            let mut root_found = header.root == 0;
+            let mut prev_free = false;
+            let mut offset = DATA;
+            while offset < self.size {
+                if self.size - offset < HEADER + MIN_SIZE {
+                    return Err(corrupt("truncated block"));
+                }
+                let block = &*self.block(offset);
+                let size = block.size as usize;
+                if size < MIN_SIZE || size & (ALIGN - 1) != 0 || size > self.size - offset - HEADER {
+                    return Err(corrupt("invalid block size"));
+                }
+                match block.state {
+                    USED => {
+                        if header.root as usize == offset + HEADER {
+                            root_found = true;
+                        }
+                        prev_free = false;
+                    }
+                    FREE => {
+                        if prev_free {
+                            return Err(corrupt("adjacent free blocks"));
+                        }
+                        if next_free != offset {
+                            return Err(corrupt("free block missing from the free list"));
+                        }
+                        next_free = self.next_free(offset);
+                        prev_free = true;
+                    }
+                    _ => return Err(corrupt("invalid block header")),
+                }
+                offset += HEADER + size;
+            }
+            if next_free != 0 {
+                return Err(corrupt("free list doesn't match the free blocks"));
+            }
+            if !root_found {
+                return Err(corrupt("root is not an allocated block"));
+            }
+        }
+        Ok(())
+    }
+
+    fn header(&self) -> *mut FileHeader {
+        self.base.as_ptr().cast()
+    }
+
+    fn block(&self, offset: usize) -> *mut Block {
+        unsafe { self.base.as_ptr().add(offset).cast() }
+    }
+
+    unsafe fn size_of(&self, offset: usize) -> usize {
+        (*self.block(offset)).size as usize
+    }
+
+    unsafe fn next_free(&self, offset: usize) -> usize {
+        *self.block(offset).add(1).cast::<u64>() as usize
+    }
+
+    unsafe fn set_next_free(&self, offset: usize, next: usize) {
+        *self.block(offset).add(1).cast::<u64>() = next as u64
+    }
+
+    /// Makes `next` follow the free block at `prev`, or the head of the
+    /// free list if `prev` is 0.
+    unsafe fn link(&self, prev: usize, next: usize) {
+        if prev == 0 {
+            (*self.header()).free = next as u64;
+        } else {
+            self.set_next_free(prev, next);
+        }
+    }
+
+    /// Returns the free blocks before and after `offset`, or 0.
+    unsafe fn free_neighbours(&self, offset: usize) -> (usize, usize) {
+        let mut prev = 0;
+        let mut next = (*self.header()).free as usize;
+        while next != 0 && next < offset {
+            prev = next;
+            next = self.next_free(next);
+        }
+        (prev, next)
+    }
+
+    /// Splits `size` bytes off the start of the block at `offset`, and
+    /// returns the offset of the rest, which is marked free but not linked.
+    unsafe fn split(&self, offset: usize, size: usize) -> usize {
+        let rest = offset + HEADER + size;
+        self.block(rest).write(Block {
+            size: (self.size_of(offset) - size - HEADER) as u64,
+            state: FREE,
+        });
+        (*self.block(offset)).size = size as u64;
+        rest
+    }
+
+    /// Frees the block at `offset`, coalescing it with its free neighbours.
+    unsafe fn release(&self, offset: usize) {
+        // A root left pointing at a free block would make the file fail
+        // `check`.
+        let header = self.header();
+        if (*header).root as usize == offset + HEADER {
+            (*header).root = 0;
+        }
+        let (prev, next) = self.free_neighbours(offset);
+        let block = self.block(offset);
+        (*block).state = FREE;
+        let mut following = next;
+        if next != 0 && next == offset + HEADER + self.size_of(offset) {
+            (*block).size += (HEADER + self.size_of(next)) as u64;
+            following = self.next_free(next);
+        }
+        if prev != 0 && prev + HEADER + self.size_of(prev) == offset {
+            (*self.block(prev)).size += (HEADER + self.size_of(offset)) as u64;
+            self.set_next_free(prev, following);
+        } else {
+            self.set_next_free(offset, following);
+            self.link(prev, offset);
+        }
+    }
+}
+
+impl Drop for PersistentHeap {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.base.as_ptr() as *mut libc::c_void, self.size) };
+    }
+}
+
+impl fmt::Debug for PersistentHeap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PersistentHeap")
+            .field("file", &self.file)
+            .field("base", &self.base)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+unsafe impl AllocRef for PersistentHeap {
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let need = adjust(layout.size()).ok_or(AllocErr)?;
+        let base = self.base.as_ptr() as usize;
+        let align = layout.align();
+        unsafe {
+            let mut prev = 0;
+            let mut offset = (*self.header()).free as usize;
+            while offset != 0 {
+                let size = self.size_of(offset);
+                let next = self.next_free(offset);
+                // Leave enough room before an aligned payload for a free
+                // block.
+                let payload = base + offset + HEADER;
+                let mut aligned = (payload + align - 1) & !(align - 1);
+                if aligned != payload && aligned - payload < HEADER + MIN_SIZE {
+                    aligned = (payload + HEADER + MIN_SIZE + align - 1) & !(align - 1);
+                }
+                let gap = aligned - payload;
+                if gap <= size && need <= size - gap {
+                    let (block, before) = if gap == 0 {
+                        self.link(prev, next);
+                        (offset, prev)
+                    } else {
+                        // The start of the block stays free.
+                        (self.split(offset, gap - HEADER), offset)
+                    };
+                    (*self.block(block)).state = USED;
+                    if self.size_of(block) >= need + HEADER + MIN_SIZE {
+                        let rest = self.split(block, need);
+                        self.set_next_free(rest, next);
+                        self.link(before, rest);
+                    } else if gap != 0 {
+                        self.set_next_free(offset, next);
+                    }
+                    let ptr = NonNull::new_unchecked(self.base.as_ptr().add(block + HEADER));
+                    return Ok((ptr, self.size_of(block)));
+                }
+                prev = offset;
+                offset = next;
+            }
+        }
+        Err(AllocErr)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, _layout: Layout) {
+        self.release(self.offset_of(ptr) - HEADER)
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        // Blocks are at least the adjusted size, and `dealloc` ignores the size.
+        (layout.size(), adjust(layout.size()).unwrap_or(layout.size()))
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        _layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let need = adjust(new_size).ok_or(CannotReallocInPlace)?;
+        let offset = self.offset_of(ptr) - HEADER;
+        let size = self.size_of(offset);
+        if need <= size {
+            return Ok(size);
+        }
+        let next = offset + HEADER + size;
+        if next >= self.size
+            || (*self.block(next)).state != FREE
+            || size + HEADER + self.size_of(next) < need
+        {
+            return Err(CannotReallocInPlace);
+        }
+        let (prev, _) = self.free_neighbours(next);
+        let following = self.next_free(next);
+        (*self.block(offset)).size += (HEADER + self.size_of(next)) as u64;
+        if self.size_of(offset) >= need + HEADER + MIN_SIZE {
+            let rest = self.split(offset, need);
+            self.set_next_free(rest, following);
+            self.link(prev, rest);
+        } else {
+            self.link(prev, following);
+        }
+        Ok(self.size_of(offset))
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        _layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let need = adjust(new_size).ok_or(CannotReallocInPlace)?;
+        let offset = self.offset_of(ptr) - HEADER;
+        if self.size_of(offset) >= need + HEADER + MIN_SIZE {
+            let rest = self.split(offset, need);
+            (*self.block(rest)).state = USED;
+            self.release(rest);
+        }
+        Ok(self.size_of(offset))
+    }
+}
+
+impl Owns for PersistentHeap {
+    fn owns(&self, ptr: NonNull<u8>, _layout: Layout) -> bool {
+        let start = self.base.as_ptr() as usize;
+        (start + DATA..start + self.size).contains(&(ptr.as_ptr() as usize))
+    }
+}
@@ -0,0 +1,455 @@
+use core::cmp;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr::{self, NonNull};
+use std::ffi::CStr;
+use std::io;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout};
+use crate::combinators::Owns;
+use crate::tlsf::Tlsf;
+
+/// A shared memory mapping.
+///
+/// The memory comes from a `memfd` or a POSIX shared memory object, and is
+/// mapped with `MAP_SHARED`, so that other processes mapping the same file
+/// descriptor or object see the same contents, usually at a different
+/// address. Data structures in the region should refer to each other with
+/// [`OffsetPtr`] and [`OffsetBox`], which hold addresses relative to the
+/// start of the mapping.
+///
+/// The mapping is unmapped, and the file descriptor closed, on drop.
+///
+/// [`OffsetPtr`]: struct.OffsetPtr.html
+/// [`OffsetBox`]: struct.OffsetBox.html
+pub struct SharedRegion {
+    fd: OwnedFd,
+    base: NonNull<u8>,
+    size: usize,
+}
+
+// The mapping is only accessed through raw pointers.
+unsafe impl Send for SharedRegion {}
+unsafe impl Sync for SharedRegion {}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+impl SharedRegion {
+    /// Creates an anonymous `memfd` of `size` bytes, and maps it.
+    ///
+    /// The region can be mapped in another process by sending it the file
+    /// descriptor, or by having it inherit it, and using [`from_fd`].
+    ///
+    /// [`from_fd`]: #method.from_fd
+    pub fn create(size: usize) -> io::Result<Self> {
+        let name = b"allocator_api\0".as_ptr().cast();
+        let fd = check(unsafe { libc::memfd_create(name, libc::MFD_CLOEXEC) })?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        check(unsafe { libc::ftruncate(fd.as_raw_fd(), size as libc::off_t) })?;
+        Self::map(fd, size)
+    }
+
+    /// Opens the POSIX shared memory object with the given name, creating
+    /// it if it doesn't exist, and maps it.
+    ///
+    /// The object is grown to `size` bytes if it is smaller, and mapped
+    /// whole. Other processes open the same object by using the same name.
+    pub fn open_named(name: &CStr, size: usize) -> io::Result<Self> {
+        let flags = libc::O_RDWR | libc::O_CREAT | libc::O_CLOEXEC;
+        let fd = check(unsafe { libc::shm_open(name.as_ptr(), flags, 0o600) })?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let current = file_size(&fd)?;
+        if current < size {
+            check(unsafe { libc::ftruncate(fd.as_raw_fd(), size as libc::off_t) })?;
+        }
+        Self::map(fd, cmp::max(current, size))
+    }
+
+    /// Removes the name of a POSIX shared memory object. The object is
+    /// destroyed once it is not mapped anymore.
+    pub fn unlink_named(name: &CStr) -> io::Result<()> {
+        check(unsafe { libc::shm_unlink(name.as_ptr()) }).map(|_| ())
+    }
+
+    /// Maps the whole file referred to by `fd`.
+    pub fn from_fd(fd: OwnedFd) -> io::Result<Self> {
+        let size = file_size(&fd)?;
+        Self::map(fd, size)
+    }
+
+    fn map(fd: OwnedFd, size: usize) -> io::Result<Self> {
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let base = unsafe { NonNull::new_unchecked(base as *mut u8) };
+        Ok(SharedRegion { fd, base, size })
+    }
+
+    /// Returns the address of the start of the mapping in this process.
+    pub fn base(&self) -> NonNull<u8> {
+        self.base
+    }
+
+    /// Returns the size of the mapping.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the address of the `size` bytes at `offset`, checking they
+    /// are in the region and suitably aligned.
+    fn resolve(&self, offset: usize, size: usize, align: usize) -> NonNull<u8> {
+        assert!(
+            offset <= self.size && size <= self.size - offset,
+            "offset {} with size {} is out of the shared region of {} bytes",
+            offset,
+            size,
+            self.size
+        );
+        let ptr = unsafe { self.base.as_ptr().add(offset) };
+        assert!(
+            ptr as usize & (align - 1) == 0,
+            "offset {} is not aligned to {}",
+            offset,
+            align
+        );
+        unsafe { NonNull::new_unchecked(ptr) }
+    }
+
+    /// Returns the offset of `ptr` from the start of the region.
+    fn offset_of(&self, ptr: *const u8) -> usize {
+        let offset = (ptr as usize).wrapping_sub(self.base.as_ptr() as usize);
+        assert!(offset < self.size, "{:p} is not in the shared region", ptr);
+        offset
+    }
+}
+
+fn file_size(fd: &OwnedFd) -> io::Result<usize> {
+    let mut stat = mem::MaybeUninit::<libc::stat>::uninit();
+    check(unsafe { libc::fstat(fd.as_raw_fd(), stat.as_mut_ptr()) })?;
+    Ok(unsafe { stat.assume_init() }.st_size as usize)
+}
+
+impl Drop for SharedRegion {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.base.as_ptr() as *mut libc::c_void, self.size) };
+    }
+}
+
+impl AsFd for SharedRegion {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for SharedRegion {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl fmt::Debug for SharedRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedRegion")
+            .field("fd", &self.fd)
+            .field("base", &self.base)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+/// An allocator managing the memory of a [`SharedRegion`].
+///
+/// Blocks are allocated with a [`Tlsf`] allocator using the whole region as
+/// its pool. The allocator's state partly lives in the process creating it,
+/// so only that process may allocate from the region. Other processes can
+/// map the region to read and write the blocks, through [`OffsetPtr`] and
+/// [`OffsetBox`].
+///
+/// [`SharedRegion`]: struct.SharedRegion.html
+/// [`Tlsf`]: ../tlsf/struct.Tlsf.html
+/// [`OffsetPtr`]: struct.OffsetPtr.html
+/// [`OffsetBox`]: struct.OffsetBox.html
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::os::{OffsetBox, SharedHeap, SharedRegion};
+/// use std::os::unix::io::AsFd;
+///
+/// let mut heap = SharedHeap::new(SharedRegion::create(1 << 16).unwrap());
+/// let mut b = OffsetBox::from_slice_in(&[1, 2, 3], &mut heap).unwrap();
+///
+/// // Another mapping of the same memory, at a different address.
+/// let fd = heap.region().as_fd().try_clone_to_owned().unwrap();
+/// let other = SharedRegion::from_fd(fd).unwrap();
+/// assert_ne!(other.base(), heap.region().base());
+/// unsafe {
+///     b.as_mut_slice(heap.region())[0] = 42;
+///     assert_eq!(b.as_slice(&other), &[42, 2, 3]);
+///     b.free_in(&mut heap);
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SharedHeap {
+    region: SharedRegion,
+    tlsf: Tlsf,
+}
+
+impl SharedHeap {
+    /// Creates an allocator managing the whole region.
+    pub fn new(region: SharedRegion) -> Self {
+        let mut tlsf = Tlsf::new();
+        unsafe { tlsf.add_pool(region.base, region.size) };
+        SharedHeap { region, tlsf }
+    }
+
+    /// Returns the region managed by the allocator.
+    pub fn region(&self) -> &SharedRegion {
+        &self.region
+    }
+
+    /// Consumes the allocator, returning the region. Blocks allocated from
+    /// the region are left as they are.
+    pub fn into_region(self) -> SharedRegion {
+        self.region
+    }
+}
+
+unsafe impl AllocRef for SharedHeap {
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.tlsf.alloc(layout)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.tlsf.dealloc(ptr, layout)
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        self.tlsf.usable_size(layout)
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.tlsf.grow_in_place(ptr, layout, new_size)
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.tlsf.shrink_in_place(ptr, layout, new_size)
+    }
+}
+
+impl Owns for SharedHeap {
+    fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        self.tlsf.owns(ptr, layout)
+    }
+}
+
+/// A pointer into a [`SharedRegion`], stored as an offset from its start.
+///
+/// Unlike a raw pointer, an `OffsetPtr` stays valid when the region is
+/// mapped at another address, including in another process. It must be
+/// resolved against a mapping of the region to be used.
+///
+/// [`SharedRegion`]: struct.SharedRegion.html
+#[repr(transparent)]
+pub struct OffsetPtr<T> {
+    offset: usize,
+    marker: PhantomData<*mut T>,
+}
+
+impl<T> OffsetPtr<T> {
+    /// Creates a pointer from an offset from the start of a region.
+    pub const fn from_offset(offset: usize) -> Self {
+        OffsetPtr { offset, marker: PhantomData }
+    }
+
+    /// Returns the offset from the start of the region.
+    pub const fn offset(self) -> usize {
+        self.offset
+    }
+
+    /// Creates a pointer to the same location as `ptr`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ptr` is not in the region.
+    pub fn new(region: &SharedRegion, ptr: NonNull<T>) -> Self {
+        OffsetPtr::from_offset(region.offset_of(ptr.as_ptr().cast()))
+    }
+
+    /// Returns the address of the pointee in the given mapping of the
+    /// region.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pointee is not entirely in the region, or is not
+    /// aligned.
+    pub fn resolve(self, region: &SharedRegion) -> NonNull<T> {
+        region
+            .resolve(self.offset, mem::size_of::<T>(), mem::align_of::<T>())
+            .cast()
+    }
+}
+
+impl<T> Clone for OffsetPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for OffsetPtr<T> {}
+
+impl<T> PartialEq for OffsetPtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.offset == other.offset
+    }
+}
+
+impl<T> Eq for OffsetPtr<T> {}
+
+impl<T> fmt::Debug for OffsetPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("OffsetPtr").field(&self.offset).finish()
+    }
+}
+
+/// An owned slice allocated in a [`SharedHeap`], referred to by its offset
+/// in the region.
+///
+/// This is the equivalent of a `Box<[T]>` that can be stored in the region
+/// itself, and read from any mapping of it. Since it doesn't hold a
+/// reference to its allocator, it is not freed on drop, and must be given
+/// back with [`free_in`].
+///
+/// [`SharedHeap`]: struct.SharedHeap.html
+/// [`free_in`]: #method.free_in
+#[repr(C)]
+pub struct OffsetBox<T> {
+    ptr: OffsetPtr<T>,
+    len: usize,
+}
+
+impl<T> OffsetBox<T> {
+    /// Moves `x` to the heap.
+    pub fn new_in(x: T, heap: &mut SharedHeap) -> Result<Self, AllocErr> {
+        let (ptr, _) = heap.alloc(Layout::new::<T>())?;
+        unsafe { ptr.as_ptr().cast::<T>().write(x) };
+        Ok(OffsetBox { ptr: OffsetPtr::new(&heap.region, ptr.cast()), len: 1 })
+    }
+
+    /// Copies the elements of `s` to the heap.
+    pub fn from_slice_in(s: &[T], heap: &mut SharedHeap) -> Result<Self, AllocErr>
+    where
+        T: Clone,
+    {
+        let layout = Layout::array::<T>(s.len()).map_err(|_| AllocErr)?;
+        let (ptr, _) = heap.alloc(layout)?;
+        let ptr = ptr.cast::<T>();
+        for (i, x) in s.iter().enumerate() {
+            unsafe { ptr.as_ptr().add(i).write(x.clone()) };
+        }
+        Ok(OffsetBox { ptr: OffsetPtr::new(&heap.region, ptr), len: s.len() })
+    }
+
+    /// Returns the number of elements.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if there are no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn resolve(&self, region: &SharedRegion) -> *mut T {
+        let size = mem::size_of::<T>() * self.len;
+        region
+            .resolve(self.ptr.offset, size, mem::align_of::<T>())
+            .as_ptr()
+            .cast()
+    }
+
+    /// Returns the elements, as seen through the given mapping of the
+    /// region.
+    ///
+    /// # Safety
+    ///
+    /// `region` must map the region the elements were allocated in, and
+    /// nothing, including in other processes, may modify the elements while
+    /// the returned slice is used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the elements are not in the region.
+    pub unsafe fn as_slice<'a>(&self, region: &'a SharedRegion) -> &'a [T] {
+        core::slice::from_raw_parts(self.resolve(region), self.len)
+    }
+
+    /// Returns the elements mutably, as seen through the given mapping of
+    /// the region.
+    ///
+    /// # Safety
+    ///
+    /// `region` must map the region the elements were allocated in, and
+    /// nothing, including in other processes, may access the elements while
+    /// the returned slice is used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the elements are not in the region.
+    pub unsafe fn as_mut_slice<'a>(&'a mut self, region: &'a SharedRegion) -> &'a mut [T] {
+        core::slice::from_raw_parts_mut(self.resolve(region), self.len)
+    }
+
+    /// Drops the elements and frees their memory.
+    ///
+    /// # Safety
+    ///
+    /// `heap` must be the allocator the elements were allocated with, and
+    /// the elements must not be used anymore, including in other processes.
+    pub unsafe fn free_in(self, heap: &mut SharedHeap) {
+        let ptr = self.resolve(&heap.region);
+        ptr::drop_in_place(ptr::slice_from_raw_parts_mut(ptr, self.len));
+        let layout = Layout::from_size_align_unchecked(mem::size_of::<T>() * self.len, mem::align_of::<T>());
+        heap.dealloc(NonNull::new_unchecked(ptr.cast()), layout);
+    }
+}
+
+impl<T> fmt::Debug for OffsetBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OffsetBox")
+            .field("offset", &self.ptr.offset)
+            .field("len", &self.len)
+            .finish()
+    }
+}
@@ -0,0 +1,202 @@
+use super::*;
+use crate::alloc::{AllocRef, Layout};
+use std::ffi::CStr;
+use std::io::{Seek, SeekFrom, Write};
+use std::string::ToString;
+use std::os::unix::io::AsFd;
+
+#[test]
+fn mmap_rounds_to_pages() {
+    let page = page_size();
+    let mut a = Mmap::new();
+    for &(size, align, pages) in &[(0, 1, 1), (1, 8, 1), (page, page, 1), (page + 1, 16, 2), (10, 4 * page, 1)] {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let (ptr, usable) = a.alloc(layout).unwrap();
+        assert_eq!(ptr.as_ptr() as usize % align, 0);
+        assert_eq!(usable, pages * page);
+        assert_eq!(a.usable_size(&layout), (size, usable));
+        unsafe {
+            ptr.as_ptr().write_bytes(0x42, usable);
+            a.dealloc(ptr, Layout::from_size_align(usable, align).unwrap());
+        }
+    }
+}
+
+#[test]
+fn mmap_remaps() {
+    let page = page_size();
+    let mut a = Mmap::new();
+    let layout = Layout::from_size_align(3 * page, 8).unwrap();
+    unsafe {
+        let (ptr, _) = a.alloc(layout).unwrap();
+        ptr.as_ptr().write_bytes(0x42, 3 * page);
+
+        assert_eq!(a.shrink_in_place(ptr, layout, page + 1), Ok(2 * page));
+        // The pages that were just unmapped are still free.
+        let layout = Layout::from_size_align(page + 1, 8).unwrap();
+        assert_eq!(a.grow_in_place_zeroed(ptr, layout, 3 * page), Ok(3 * page));
+        assert_eq!(*ptr.as_ptr().add(page), 0x42);
+        assert_eq!(*ptr.as_ptr().add(page + 1), 0);
+        assert_eq!(*ptr.as_ptr().add(3 * page - 1), 0);
+
+        let layout = Layout::from_size_align(3 * page, 8).unwrap();
+        let (ptr, size) = a.realloc(ptr, layout, 1000 * page).unwrap();
+        assert_eq!(size, 1000 * page);
+        assert_eq!(*ptr.as_ptr().add(page), 0x42);
+        a.dealloc(ptr, Layout::from_size_align(size, 8).unwrap());
+    }
+}
+
+#[test]
+fn mmap_realloc_keeps_large_alignment() {
+    let page = page_size();
+    let mut a = Mmap::new().huge_pages();
+    let align = 1 << 21;
+    let layout = Layout::from_size_align(10, align).unwrap();
+    unsafe {
+        let (ptr, _) = a.alloc(layout).unwrap();
+        ptr.as_ptr().write_bytes(0x42, page);
+        let (ptr, size) = a.realloc_zeroed(ptr, layout, 4 << 20).unwrap();
+        assert_eq!(ptr.as_ptr() as usize % align, 0);
+        assert_eq!(size, 4 << 20);
+        assert_eq!(*ptr.as_ptr().add(9), 0x42);
+        assert_eq!(*ptr.as_ptr().add(10), 0);
+        let layout = Layout::from_size_align(size, align).unwrap();
+        assert_eq!(a.shrink_in_place(ptr, layout, 1), Ok(page));
+        a.dealloc(ptr, Layout::from_size_align(1, align).unwrap());
+    }
+}
+
+#[test]
+fn shared_heap_across_fork() {
+    let mut heap = SharedHeap::new(SharedRegion::create(1 << 16).unwrap());
+    let mut b = OffsetBox::from_slice_in(&[1u64, 2, 3], &mut heap).unwrap();
+    let fd = heap.region().as_fd().try_clone_to_owned().unwrap();
+    unsafe {
+        let pid = libc::fork();
+        if pid == 0 {
+            // Map the region again, at a different address.
+            let region = SharedRegion::from_fd(fd).unwrap();
+            let ok = region.base() != heap.region().base() && b.as_slice(&region) == [1, 2, 3];
+            b.as_mut_slice(&region)[0] = 42;
+            libc::_exit(if ok { 0 } else { 1 });
+        }
+        let mut status = 0;
+        libc::waitpid(pid, &mut status, 0);
+        assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+        assert_eq!(b.as_slice(heap.region()), [42, 2, 3]);
+        b.free_in(&mut heap);
+    }
+}
+
+#[test]
+fn shared_region_offsets() {
+    let name = CStr::from_bytes_with_nul(b"/allocator_api_shared_region_offsets\0").unwrap();
+    let mut heap = SharedHeap::new(SharedRegion::open_named(name, 1 << 16).unwrap());
+    let other = SharedRegion::open_named(name, 0).unwrap();
+    SharedRegion::unlink_named(name).unwrap();
+    assert_eq!(other.size(), 1 << 16);
+
+    let b = OffsetBox::new_in(0x1234_5678u32, &mut heap).unwrap();
+    unsafe { assert_eq!(b.as_slice(&other), [0x1234_5678]) };
+
+    let (ptr, _) = heap.alloc(Layout::new::<u64>()).unwrap();
+    let offset_ptr = OffsetPtr::new(heap.region(), ptr.cast::<u64>());
+    unsafe { offset_ptr.resolve(heap.region()).as_ptr().write(7) };
+    assert_eq!(unsafe { *offset_ptr.resolve(&other).as_ptr() }, 7);
+    assert!(std::panic::catch_unwind(|| OffsetPtr::<u64>::from_offset(1 << 16).resolve(&other)).is_err());
+    unsafe {
+        heap.dealloc(ptr, Layout::new::<u64>());
+        b.free_in(&mut heap);
+    }
+}
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(std::format!("allocator_api-{}-{}", name, std::process::id()))
+}
+
+#[test]
+fn persistent_heap_reopens() {
+    let path = temp_path("persistent_heap_reopens");
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    let kept = {
+        let mut heap = PersistentHeap::create(&path, 1 << 16).unwrap();
+        let (a, size) = heap.alloc(layout).unwrap();
+        assert_eq!(size, 112);
+        let (b, _) = heap.alloc(Layout::from_size_align(10, 4096).unwrap()).unwrap();
+        assert_eq!(b.as_ptr() as usize % 4096, 0);
+        let (c, _) = heap.alloc(layout).unwrap();
+        unsafe {
+            c.as_ptr().write_bytes(0x42, 100);
+            heap.dealloc(a, layout);
+            assert_eq!(heap.grow_in_place(c, layout, 1000), Ok(1008));
+            assert_eq!(heap.shrink_in_place(c, Layout::from_size_align(1000, 8).unwrap(), 200), Ok(208));
+        }
+        heap.set_root(Some(c));
+        heap.check().unwrap();
+        heap.flush().unwrap();
+        (heap.offset_of(b), heap.offset_of(c))
+    };
+
+    let mut heap = PersistentHeap::open(&path).unwrap();
+    let c = heap.root().unwrap();
+    assert_eq!(heap.offset_of(c), kept.1);
+    assert_eq!(unsafe { *c.as_ptr().add(99) }, 0x42);
+    // The blocks that were allocated are still allocated.
+    let (d, _) = heap.alloc(Layout::from_size_align(16, 16).unwrap()).unwrap();
+    assert!(heap.offset_of(d) != kept.0 && heap.offset_of(d) != kept.1);
+    unsafe {
+        heap.dealloc(d, Layout::from_size_align(16, 16).unwrap());
+        heap.dealloc(heap.at_offset(kept.0), Layout::from_size_align(10, 4096).unwrap());
+        heap.dealloc(c, Layout::from_size_align(200, 8).unwrap());
+    }
+    heap.set_root(None);
+    heap.check().unwrap();
+    // Everything was coalesced back in a single block.
+    let (all, size) = heap.alloc(Layout::from_size_align(1, 1).unwrap()).unwrap();
+    assert_eq!(size, 16);
+    let rest = (1 << 16) - 80;
+    let layout = Layout::from_size_align(16, 1).unwrap();
+    assert_eq!(unsafe { heap.grow_in_place(all, layout, rest) }, Ok(rest));
+    drop(heap);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn persistent_heap_forgets_freed_root() {
+    let path = temp_path("persistent_heap_forgets_freed_root");
+    let layout = Layout::new::<u64>();
+    {
+        let mut heap = PersistentHeap::create(&path, 4096).unwrap();
+        let (a, _) = heap.alloc(layout).unwrap();
+        heap.set_root(Some(a));
+        unsafe { heap.dealloc(a, layout) };
+        assert_eq!(heap.root(), None);
+        heap.check().unwrap();
+        heap.flush().unwrap();
+    }
+    let heap = PersistentHeap::open(&path).unwrap();
+    assert_eq!(heap.root(), None);
+    drop(heap);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn persistent_heap_detects_corruption() {
+    let path = temp_path("persistent_heap_detects_corruption");
+    let offset = {
+        let mut heap = PersistentHeap::create(&path, 4096).unwrap();
+        let (a, _) = heap.alloc(Layout::new::<u64>()).unwrap();
+        heap.offset_of(a)
+    };
+    PersistentHeap::open(&path).unwrap();
+    // Overwrite the header of the block.
+    let mut file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(SeekFrom::Start(offset as u64 - 8)).unwrap();
+    file.write_all(&[0; 8]).unwrap();
+    drop(file);
+    let err = PersistentHeap::open(&path).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "invalid block header");
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,418 @@
+//! Allocation statistics.
+//!
+//! [`Counting`] wraps any `AllocRef` and records statistics about the calls
+//! going through it in a [`Stats`] structure. The counters are atomic, so a
+//! [`Snapshot`] can be taken from any thread while the allocator is in use.
+//!
+//! [`Counting`]: struct.Counting.html
+//! [`Stats`]: struct.Stats.html
+//! [`Snapshot`]: struct.Snapshot.html
+
+use core::cmp;
+use core::fmt;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout};
+use crate::combinators::Owns;
+
+/// Number of size classes in the histogram.
+///
+/// Size class `0` counts zero-sized requests, and size class `n` counts
+/// requests for sizes in the range `[2^(n-1), 2^n)`.
+pub const SIZE_CLASSES: usize = usize::BITS as usize + 1;
+
+/// Returns the size class for the given size.
+///
+/// See [`SIZE_CLASSES`](constant.SIZE_CLASSES.html).
+#[inline]
+pub fn size_class(size: usize) -> usize {
+    (usize::BITS - size.leading_zeros()) as usize
+}
+
+/// Counters for allocations going through one or more [`Counting`]
+/// allocators.
+///
+/// The size histogram records the sizes given by callers, while live and
+/// peak bytes account for blocks with the largest usable size the wrapped
+/// allocator reports for them (see `AllocRef::usable_size`), whichever of
+/// their usable sizes they are deallocated with.
+///
+/// [`Counting`]: struct.Counting.html
+pub struct Stats {
+    live_bytes: AtomicUsize,
+    peak_bytes: AtomicUsize,
+    allocs: AtomicUsize,
+    failed_allocs: AtomicUsize,
+    deallocs: AtomicUsize,
+    reallocs: AtomicUsize,
+    failed_reallocs: AtomicUsize,
+    reallocs_in_place: AtomicUsize,
+    grows_in_place: AtomicUsize,
+    failed_grows_in_place: AtomicUsize,
+    shrinks_in_place: AtomicUsize,
+    failed_shrinks_in_place: AtomicUsize,
+    histogram: [AtomicUsize; SIZE_CLASSES],
+}
+
+/// A copy of the counters of a [`Stats`] at a given point in time.
+///
+/// [`Stats`]: struct.Stats.html
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Snapshot {
+    /// Number of bytes currently allocated.
+    pub live_bytes: usize,
+    /// Highest value `live_bytes` has reached.
+    pub peak_bytes: usize,
+    /// Number of successful calls to `alloc` and `alloc_zeroed`.
+    pub allocs: usize,
+    /// Number of failed calls to `alloc` and `alloc_zeroed`.
+    pub failed_allocs: usize,
+    /// Number of calls to `dealloc`.
+    pub deallocs: usize,
+    /// Number of successful calls to `realloc` and `realloc_zeroed`.
+    pub reallocs: usize,
+    /// Number of failed calls to `realloc` and `realloc_zeroed`.
+    pub failed_reallocs: usize,
+    /// Number of successful calls to `realloc` and `realloc_zeroed` that
+    /// kept the block at the same address.
+    pub reallocs_in_place: usize,
+    /// Number of successful calls to `grow_in_place` and `grow_in_place_zeroed`.
+    pub grows_in_place: usize,
+    /// Number of failed calls to `grow_in_place` and `grow_in_place_zeroed`.
+    pub failed_grows_in_place: usize,
+    /// Number of successful calls to `shrink_in_place`.
+    pub shrinks_in_place: usize,
+    /// Number of failed calls to `shrink_in_place`.
+    pub failed_shrinks_in_place: usize,
+    /// Number of allocation and reallocation requests per size class.
+    ///
+    /// See [`size_class`](fn.size_class.html).
+    pub histogram: [usize; SIZE_CLASSES],
+}
+
+impl Stats {
+    /// Creates a new set of counters, all zero.
+    #[allow(clippy::declare_interior_mutable_const)]
+    pub const fn new() -> Self {
+        const ZERO: AtomicUsize = AtomicUsize::new(0);
+        Stats {
+            live_bytes: ZERO,
+            peak_bytes: ZERO,
+            allocs: ZERO,
+            failed_allocs: ZERO,
+            deallocs: ZERO,
+            reallocs: ZERO,
+            failed_reallocs: ZERO,
+            reallocs_in_place: ZERO,
+            grows_in_place: ZERO,
+            failed_grows_in_place: ZERO,
+            shrinks_in_place: ZERO,
+            failed_shrinks_in_place: ZERO,
+            histogram: [ZERO; SIZE_CLASSES],
+        }
+    }
+
+    /// Returns a copy of the current value of the counters.
+    ///
+    /// The counters are read individually, so the snapshot may not be
+    /// consistent if allocations happen concurrently.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut histogram = [0; SIZE_CLASSES];
+        for (h, counter) in histogram.iter_mut().zip(self.histogram.iter()) {
+            *h = counter.load(Ordering::Relaxed);
+        }
+        Snapshot {
+            live_bytes: self.live_bytes.load(Ordering::Relaxed),
+            peak_bytes: self.peak_bytes.load(Ordering::Relaxed),
+            allocs: self.allocs.load(Ordering::Relaxed),
+            failed_allocs: self.failed_allocs.load(Ordering::Relaxed),
+            deallocs: self.deallocs.load(Ordering::Relaxed),
+            reallocs: self.reallocs.load(Ordering::Relaxed),
+            failed_reallocs: self.failed_reallocs.load(Ordering::Relaxed),
+            reallocs_in_place: self.reallocs_in_place.load(Ordering::Relaxed),
+            grows_in_place: self.grows_in_place.load(Ordering::Relaxed),
+            failed_grows_in_place: self.failed_grows_in_place.load(Ordering::Relaxed),
+            shrinks_in_place: self.shrinks_in_place.load(Ordering::Relaxed),
+            failed_shrinks_in_place: self.failed_shrinks_in_place.load(Ordering::Relaxed),
+            histogram,
+        }
+    }
+
+    fn grow(&self, size: usize) {
+        let live = self.live_bytes.fetch_add(size, Ordering::Relaxed) + size;
+        self.peak_bytes.fetch_max(live, Ordering::Relaxed);
+    }
+
+    fn shrink(&self, size: usize) {
+        self.live_bytes.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    fn resize(&self, old_size: usize, new_size: usize) {
+        if new_size > old_size {
+            self.grow(new_size - old_size);
+        } else {
+            self.shrink(old_size - new_size);
+        }
+    }
+
+    fn record_size(&self, size: usize) {
+        self.histogram[size_class(size)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn count<T, E>(
+        &self,
+        result: &Result<T, E>,
+        success: &AtomicUsize,
+        failure: &AtomicUsize,
+    ) -> bool {
+        let counter = if result.is_ok() { success } else { failure };
+        counter.fetch_add(1, Ordering::Relaxed);
+        result.is_ok()
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats::new()
+    }
+}
+
+impl fmt::Debug for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.snapshot().fmt(f)
+    }
+}
+
+impl Snapshot {
+    /// Returns the ratio of successful `realloc` calls that kept the block
+    /// in place, or `None` if there were none.
+    pub fn realloc_in_place_rate(&self) -> Option<f64> {
+        rate(self.reallocs_in_place, self.reallocs.saturating_sub(self.reallocs_in_place))
+    }
+
+    /// Returns the ratio of successful `grow_in_place` calls, or `None` if
+    /// there were none.
+    pub fn grow_in_place_success_rate(&self) -> Option<f64> {
+        rate(self.grows_in_place, self.failed_grows_in_place)
+    }
+
+    /// Returns the ratio of successful `shrink_in_place` calls, or `None`
+    /// if there were none.
+    pub fn shrink_in_place_success_rate(&self) -> Option<f64> {
+        rate(self.shrinks_in_place, self.failed_shrinks_in_place)
+    }
+}
+
+fn rate(success: usize, failure: usize) -> Option<f64> {
+    let total = success + failure;
+    if total == 0 {
+        None
+    } else {
+        Some(success as f64 / total as f64)
+    }
+}
+
+/// An allocator wrapper recording statistics in a [`Stats`].
+///
+/// Several `Counting` allocators may share the same `Stats`, which allows
+/// accounting for all the allocations of a subsystem, whichever allocator
+/// handle they go through.
+///
+/// [`Stats`]: struct.Stats.html
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::stats::{Counting, Stats};
+/// use allocator_api::{Box, Global, RawVec};
+///
+/// static STATS: Stats = Stats::new();
+///
+/// let b = Box::new_in(42u64, Counting::new(Global, &STATS));
+/// let mut v: RawVec<u8, _> = RawVec::with_capacity_in(16, Counting::new(Global, &STATS));
+/// v.reserve(16, 48);
+///
+/// let snapshot = STATS.snapshot();
+/// assert_eq!(snapshot.allocs, 2);
+/// assert_eq!(snapshot.reallocs, 1);
+/// assert_eq!(snapshot.live_bytes, 8 + 64);
+///
+/// drop((b, v));
+/// let snapshot = STATS.snapshot();
+/// assert_eq!(snapshot.live_bytes, 0);
+/// assert_eq!(snapshot.peak_bytes, 8 + 64);
+/// assert_eq!(snapshot.deallocs, 2);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Counting<'s, A> {
+    a: A,
+    stats: &'s Stats,
+}
+
+impl<'s, A: AllocRef> Counting<'s, A> {
+    /// Wraps the given allocator, recording statistics in `stats`.
+    pub fn new(a: A, stats: &'s Stats) -> Self {
+        Counting { a, stats }
+    }
+
+    /// Returns the statistics this allocator records to.
+    pub fn stats(&self) -> &'s Stats {
+        self.stats
+    }
+
+    /// Returns a reference to the wrapped allocator.
+    pub fn get_ref(&self) -> &A {
+        &self.a
+    }
+
+    /// Consumes the wrapper, returning the wrapped allocator.
+    pub fn into_inner(self) -> A {
+        self.a
+    }
+
+    /// Returns the size accounted for blocks of the given size and
+    /// alignment.
+    fn live_size(&self, size: usize, align: usize) -> usize {
+        let layout = unsafe { Layout::from_size_align_unchecked(size, align) };
+        self.a.usable_size(&layout).1
+    }
+
+    fn after_alloc(
+        &self,
+        layout: Layout,
+        result: Result<(NonNull<u8>, usize), AllocErr>,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let stats = self.stats;
+        stats.record_size(layout.size());
+        let live = self.live_size(layout.size(), layout.align());
+        if stats.count(&result, &stats.allocs, &stats.failed_allocs) {
+            stats.grow(live);
+        }
+        result.map(|(ptr, size)| (ptr, cmp::min(size, live)))
+    }
+
+    fn after_realloc(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+        result: Result<(NonNull<u8>, usize), AllocErr>,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let stats = self.stats;
+        stats.record_size(new_size);
+        let live = self.live_size(new_size, layout.align());
+        if stats.count(&result, &stats.reallocs, &stats.failed_reallocs) {
+            stats.resize(self.live_size(layout.size(), layout.align()), live);
+        }
+        if let Ok((new_ptr, _)) = result {
+            if new_ptr == ptr {
+                stats.reallocs_in_place.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        result.map(|(ptr, size)| (ptr, cmp::min(size, live)))
+    }
+
+    fn after_grow_in_place(
+        &self,
+        layout: Layout,
+        new_size: usize,
+        result: Result<usize, CannotReallocInPlace>,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let stats = self.stats;
+        let live = self.live_size(new_size, layout.align());
+        if stats.count(&result, &stats.grows_in_place, &stats.failed_grows_in_place) {
+            stats.resize(self.live_size(layout.size(), layout.align()), live);
+        }
+        result.map(|size| cmp::min(size, live))
+    }
+}
+
+unsafe impl<A: AllocRef> AllocRef for Counting<'_, A> {
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let result = self.a.alloc(layout);
+        self.after_alloc(layout, result)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.a.dealloc(ptr, layout);
+        self.stats.deallocs.fetch_add(1, Ordering::Relaxed);
+        self.stats.shrink(self.live_size(layout.size(), layout.align()));
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        self.a.usable_size(layout)
+    }
+
+    fn alloc_zeroed(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let result = self.a.alloc_zeroed(layout);
+        self.after_alloc(layout, result)
+    }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let result = self.a.realloc(ptr, layout, new_size);
+        self.after_realloc(ptr, layout, new_size, result)
+    }
+
+    unsafe fn realloc_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let result = self.a.realloc_zeroed(ptr, layout, new_size);
+        self.after_realloc(ptr, layout, new_size, result)
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let result = self.a.grow_in_place(ptr, layout, new_size);
+        self.after_grow_in_place(layout, new_size, result)
+    }
+
+    unsafe fn grow_in_place_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let result = self.a.grow_in_place_zeroed(ptr, layout, new_size);
+        self.after_grow_in_place(layout, new_size, result)
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let result = self.a.shrink_in_place(ptr, layout, new_size);
+        let stats = self.stats;
+        let live = self.live_size(new_size, layout.align());
+        if stats.count(&result, &stats.shrinks_in_place, &stats.failed_shrinks_in_place) {
+            stats.resize(self.live_size(layout.size(), layout.align()), live);
+        }
+        result.map(|size| cmp::min(size, live))
+    }
+}
+
+impl<A: Owns> Owns for Counting<'_, A> {
+    fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        self.a.owns(ptr, layout)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests;
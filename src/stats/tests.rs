@@ -0,0 +1,92 @@
+use super::*;
+use crate::alloc::Global;
+use crate::bitmap::Bitmap;
+use crate::boxed::Box;
+use crate::raw_vec::RawVec;
+use crate::sync::SpinLocked;
+
+use std::thread;
+
+#[test]
+fn counting_snapshots_across_threads() {
+    static STATS: Stats = Stats::new();
+    const THREADS: usize = 4;
+    const ROUNDS: usize = 1000;
+
+    let handles: std::vec::Vec<_> = (0..THREADS)
+        .map(|i| {
+            thread::spawn(move || {
+                for j in 0..ROUNDS {
+                    let b = Box::new_in([i as u8; 24], Counting::new(Global, &STATS));
+                    let mut v: RawVec<u8, _> = RawVec::with_capacity_in(8, Counting::new(Global, &STATS));
+                    v.reserve_exact(8, j % 8 + 1);
+                    drop((b, v));
+                }
+            })
+        })
+        .collect();
+    // Snapshots can be taken while the counters are being updated.
+    let mut allocs = 0;
+    while !handles.iter().all(|h| h.is_finished()) {
+        let snapshot = STATS.snapshot();
+        assert!(snapshot.allocs >= allocs);
+        allocs = snapshot.allocs;
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+
+    let snapshot = STATS.snapshot();
+    assert_eq!(snapshot.allocs, 2 * THREADS * ROUNDS);
+    assert_eq!(snapshot.deallocs, 2 * THREADS * ROUNDS);
+    assert_eq!(snapshot.reallocs, THREADS * ROUNDS);
+    assert_eq!(snapshot.failed_allocs + snapshot.failed_reallocs, 0);
+    assert_eq!(snapshot.live_bytes, 0);
+    assert!(snapshot.peak_bytes >= 24 + 9 && snapshot.peak_bytes <= THREADS * (24 + 16));
+    assert_eq!(snapshot.histogram.iter().sum::<usize>(), 3 * THREADS * ROUNDS);
+}
+
+#[test]
+fn counting_classifies_reallocs() {
+    let stats = Stats::new();
+    let bitmap: Bitmap<16> = Bitmap::from_alloc(&mut Global, 4096).unwrap();
+    let locked = SpinLocked::new(bitmap);
+
+    let mut v: RawVec<u8, _> = RawVec::with_capacity_in(16, Counting::new(&locked, &stats));
+    // The following granule is free, so the block grows in place.
+    v.reserve_exact(16, 16);
+    assert_eq!(v.capacity(), 32);
+    let snapshot = stats.snapshot();
+    assert_eq!((snapshot.reallocs, snapshot.reallocs_in_place), (1, 1));
+    assert_eq!(snapshot.realloc_in_place_rate(), Some(1.0));
+    // Only direct calls count as in-place attempts.
+    assert_eq!(snapshot.grow_in_place_success_rate(), None);
+
+    // The following granule is used, so the block moves.
+    let b = Box::new_in([0u8; 16], Counting::new(&locked, &stats));
+    let old = v.ptr();
+    v.reserve_exact(32, 32);
+    assert!(v.ptr() != old);
+    let snapshot = stats.snapshot();
+    assert_eq!((snapshot.reallocs, snapshot.failed_reallocs), (2, 0));
+    assert_eq!(snapshot.realloc_in_place_rate(), Some(0.5));
+    assert_eq!(snapshot.live_bytes, 64 + 16);
+
+    v.shrink_to_fit(16);
+    let snapshot = stats.snapshot();
+    assert_eq!((snapshot.reallocs, snapshot.reallocs_in_place), (3, 2));
+    assert_eq!(snapshot.live_bytes, 16 + 16);
+
+    // Direct calls are counted on their own.
+    assert!(v.reserve_in_place(16, 16));
+    assert!(!v.reserve_in_place(32, 8192));
+    let snapshot = stats.snapshot();
+    assert_eq!((snapshot.grows_in_place, snapshot.failed_grows_in_place), (1, 1));
+    assert_eq!(snapshot.reallocs, 3);
+
+    drop((b, v));
+    assert_eq!(stats.snapshot().live_bytes, 0);
+    let bitmap = locked.into_inner();
+    assert_eq!(bitmap.usage().used_granules, 0);
+    unsafe { bitmap.free_region(&mut Global) };
+}
@@ -0,0 +1,289 @@
+//! Allocator wrappers allowing to share an allocator between threads.
+//!
+//! `AllocRef` methods take `&mut self`, so an allocator can't be used from
+//! several places at once without some form of locking. [`SpinLocked`] and
+//! [`MutexLocked`] wrap an allocator in a lock, and implement
+//! `SharedAllocRef`, which means references to them implement `AllocRef`.
+//! Such references can be given to as many `Box` or `RawVec` as needed,
+//! including on different threads.
+//!
+//! [`SpinLocked`]: struct.SpinLocked.html
+//! [`MutexLocked`]: struct.MutexLocked.html
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::hint;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout, SharedAllocRef};
+use crate::combinators::Owns;
+
+/// An allocator wrapper protected by a spin lock.
+///
+/// This doesn't require the standard library, but spinning wastes CPU time
+/// when the lock is contended. Prefer [`MutexLocked`] when the standard
+/// library is available and contention is expected.
+///
+/// [`MutexLocked`]: struct.MutexLocked.html
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::stats::{Counting, Stats};
+/// use allocator_api::sync::SpinLocked;
+/// use allocator_api::{Box, Global};
+///
+/// let stats = Stats::new();
+/// let a = &SpinLocked::new(Counting::new(Global, &stats));
+/// let sum: u32 = std::thread::scope(|s| {
+///     let threads: Vec<_> = (0..4).map(|i| s.spawn(move || *Box::new_in(i, a))).collect();
+///     threads.into_iter().map(|t| t.join().unwrap()).sum()
+/// });
+/// assert_eq!(sum, 6);
+/// assert_eq!(stats.snapshot().allocs, 4);
+/// # }
+/// ```
+pub struct SpinLocked<A> {
+    locked: AtomicBool,
+    a: UnsafeCell<A>,
+}
+
+unsafe impl<A: Send> Sync for SpinLocked<A> {}
+
+/// A guard giving exclusive access to the allocator wrapped in a
+/// [`SpinLocked`], until it is dropped.
+///
+/// Like `std::sync::MutexGuard`, the guard is only `Sync` when the
+/// allocator is, since sharing the guard shares the allocator:
+///
+/// ```compile_fail
+/// use allocator_api::sync::SpinLockGuard;
+/// use std::cell::Cell;
+///
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<SpinLockGuard<'static, Cell<usize>>>();
+/// ```
+///
+/// [`SpinLocked`]: struct.SpinLocked.html
+pub struct SpinLockGuard<'a, A> {
+    lock: &'a SpinLocked<A>,
+    // Opts out of the `Send` and `Sync` impls derived from the reference.
+    marker: PhantomData<*mut ()>,
+}
+
+unsafe impl<A: Sync> Sync for SpinLockGuard<'_, A> {}
+
+impl<A> SpinLocked<A> {
+    /// Wraps the given allocator.
+    pub const fn new(a: A) -> Self {
+        SpinLocked { locked: AtomicBool::new(false), a: UnsafeCell::new(a) }
+    }
+
+    /// Consumes the wrapper, returning the wrapped allocator.
+    pub fn into_inner(self) -> A {
+        self.a.into_inner()
+    }
+
+    /// Returns a mutable reference to the wrapped allocator.
+    ///
+    /// Since this call borrows the wrapper mutably, no locking needs to
+    /// take place.
+    pub fn get_mut(&mut self) -> &mut A {
+        self.a.get_mut()
+    }
+
+    /// Spins until the lock is acquired, and returns a guard giving
+    /// exclusive access to the wrapped allocator.
+    pub fn lock(&self) -> SpinLockGuard<'_, A> {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                hint::spin_loop();
+            }
+        }
+        SpinLockGuard { lock: self, marker: PhantomData }
+    }
+}
+
+impl<A> Deref for SpinLockGuard<'_, A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        unsafe { &*self.lock.a.get() }
+    }
+}
+
+impl<A> DerefMut for SpinLockGuard<'_, A> {
+    fn deref_mut(&mut self) -> &mut A {
+        unsafe { &mut *self.lock.a.get() }
+    }
+}
+
+impl<A> Drop for SpinLockGuard<'_, A> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
+
+impl<A: Default> Default for SpinLocked<A> {
+    fn default() -> Self {
+        SpinLocked::new(A::default())
+    }
+}
+
+impl<A> fmt::Debug for SpinLocked<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpinLocked")
+            .field("locked", &self.locked.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+/// An allocator wrapper protected by a `std::sync::Mutex`.
+///
+/// A panic while the lock is held doesn't poison the wrapper: allocators
+/// are expected to be left in a consistent state when a panic unwinds
+/// through them.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::sync::MutexLocked;
+/// use allocator_api::{Global, RawVec};
+/// use std::sync::Arc;
+///
+/// let a = Arc::new(MutexLocked::new(Global));
+/// let threads: Vec<_> = (0..4)
+///     .map(|_| {
+///         let a = a.clone();
+///         std::thread::spawn(move || {
+///             let mut v: RawVec<u8, _> = RawVec::new_in(&*a);
+///             v.reserve(0, 100);
+///             v.capacity()
+///         })
+///     })
+///     .collect();
+/// for t in threads {
+///     assert_eq!(t.join().unwrap(), 100);
+/// }
+/// # }
+/// ```
+#[cfg(feature = "std")]
+pub struct MutexLocked<A> {
+    a: std::sync::Mutex<A>,
+}
+
+#[cfg(feature = "std")]
+impl<A> MutexLocked<A> {
+    /// Wraps the given allocator.
+    pub const fn new(a: A) -> Self {
+        MutexLocked { a: std::sync::Mutex::new(a) }
+    }
+
+    /// Consumes the wrapper, returning the wrapped allocator.
+    pub fn into_inner(self) -> A {
+        self.a.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns a mutable reference to the wrapped allocator.
+    ///
+    /// Since this call borrows the wrapper mutably, no locking needs to
+    /// take place.
+    pub fn get_mut(&mut self) -> &mut A {
+        self.a.get_mut().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Blocks until the lock is acquired, and returns a guard giving
+    /// exclusive access to the wrapped allocator.
+    pub fn lock(&self) -> std::sync::MutexGuard<'_, A> {
+        self.a.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: Default> Default for MutexLocked<A> {
+    fn default() -> Self {
+        MutexLocked::new(A::default())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A> fmt::Debug for MutexLocked<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MutexLocked").finish()
+    }
+}
+
+macro_rules! locked_alloc_ref {
+    ($($(#[$attr:meta])* $t:ident),*) => {
+        $(
+            $(#[$attr])*
+            unsafe impl<A: AllocRef> SharedAllocRef for $t<A> {
+                fn alloc(&self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+                    self.lock().alloc(layout)
+                }
+
+                unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+                    self.lock().dealloc(ptr, layout)
+                }
+
+                fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+                    self.lock().usable_size(layout)
+                }
+
+                fn alloc_zeroed(&self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+                    self.lock().alloc_zeroed(layout)
+                }
+
+                unsafe fn realloc(
+                    &self,
+                    ptr: NonNull<u8>,
+                    layout: Layout,
+                    new_size: usize,
+                ) -> Result<(NonNull<u8>, usize), AllocErr> {
+                    self.lock().realloc(ptr, layout, new_size)
+                }
+
+                unsafe fn grow_in_place(
+                    &self,
+                    ptr: NonNull<u8>,
+                    layout: Layout,
+                    new_size: usize,
+                ) -> Result<usize, CannotReallocInPlace> {
+                    self.lock().grow_in_place(ptr, layout, new_size)
+                }
+
+                unsafe fn shrink_in_place(
+                    &self,
+                    ptr: NonNull<u8>,
+                    layout: Layout,
+                    new_size: usize,
+                ) -> Result<usize, CannotReallocInPlace> {
+                    self.lock().shrink_in_place(ptr, layout, new_size)
+                }
+            }
+
+            $(#[$attr])*
+            impl<A: Owns> Owns for $t<A> {
+                fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+                    self.lock().owns(ptr, layout)
+                }
+            }
+        )*
+    };
+}
+
+locked_alloc_ref!(SpinLocked, #[cfg(feature = "std")] MutexLocked);
+
+#[cfg(all(test, feature = "std"))]
+mod tests;
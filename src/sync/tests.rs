@@ -0,0 +1,57 @@
+use super::*;
+use crate::alloc::Global;
+use crate::bitmap::Bitmap;
+
+use std::thread;
+
+const THREADS: usize = 4;
+const ROUNDS: usize = 1000;
+
+/// Allocates and deallocates blocks through `&A`, as an `AllocRef`, from
+/// several threads at once, checking that no block is handed out twice.
+fn hammer<A: SharedAllocRef + Sync>(a: &A) {
+    thread::scope(|s| {
+        for id in 0..THREADS {
+            s.spawn(move || {
+                let mut a = a;
+                let mut blocks = std::vec::Vec::new();
+                for i in 0..ROUNDS {
+                    let layout = Layout::from_size_align(16 + i % 4 * 16, 16).unwrap();
+                    let (ptr, _) = AllocRef::alloc(&mut a, layout).unwrap();
+                    unsafe { ptr.as_ptr().write_bytes(id as u8, layout.size()) };
+                    blocks.push((ptr, layout));
+                    if blocks.len() == 8 {
+                        for (ptr, layout) in blocks.drain(..) {
+                            let bytes = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), layout.size()) };
+                            assert!(bytes.iter().all(|&b| b == id as u8));
+                            unsafe { AllocRef::dealloc(&mut a, ptr, layout) };
+                        }
+                    }
+                }
+                for (ptr, layout) in blocks {
+                    unsafe { AllocRef::dealloc(&mut a, ptr, layout) };
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn spin_locked_shared_between_threads() {
+    let bitmap: Bitmap<16> = Bitmap::from_alloc(&mut Global, 64 * 1024).unwrap();
+    let locked = SpinLocked::new(bitmap);
+    hammer(&locked);
+    let bitmap = locked.into_inner();
+    assert_eq!(bitmap.usage().used_granules, 0);
+    unsafe { bitmap.free_region(&mut Global) };
+}
+
+#[test]
+fn mutex_locked_shared_between_threads() {
+    let bitmap: Bitmap<16> = Bitmap::from_alloc(&mut Global, 64 * 1024).unwrap();
+    let locked = MutexLocked::new(bitmap);
+    hammer(&locked);
+    let bitmap = locked.into_inner();
+    assert_eq!(bitmap.usage().used_granules, 0);
+    unsafe { bitmap.free_region(&mut Global) };
+}
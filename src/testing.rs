@@ -0,0 +1,378 @@
+//! Helpers for testing allocation failure handling.
+//!
+//! [`FailingAlloc`] wraps any `AllocRef` and makes some of the calls going
+//! through it fail, as decided by a [`Faults`] schedule. This allows to
+//! deterministically exercise the error paths of code using fallible
+//! allocation APIs, such as `RawVec::try_reserve`.
+//!
+//! [`FailingAlloc`]: struct.FailingAlloc.html
+//! [`Faults`]: struct.Faults.html
+
+use core::fmt;
+use core::ops::BitOr;
+use core::ptr::NonNull;
+#[cfg(target_has_atomic = "64")]
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout};
+use crate::combinators::Owns;
+#[cfg(feature = "std")]
+use crate::debug::LeakCheck;
+
+/// A set of `AllocRef` operations.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Ops(u8);
+
+impl Ops {
+    /// `alloc` and `alloc_zeroed`.
+    pub const ALLOC: Ops = Ops(1);
+    /// `realloc` and `realloc_zeroed`.
+    pub const REALLOC: Ops = Ops(2);
+    /// `grow_in_place` and `grow_in_place_zeroed`.
+    pub const GROW_IN_PLACE: Ops = Ops(4);
+    /// `shrink_in_place`.
+    pub const SHRINK_IN_PLACE: Ops = Ops(8);
+    /// All the operations that can fail.
+    pub const ALL: Ops = Ops(15);
+
+    /// Returns whether all the operations in `other` are in `self`.
+    pub fn contains(self, other: Ops) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Ops {
+    type Output = Ops;
+
+    fn bitor(self, other: Ops) -> Ops {
+        Ops(self.0 | other.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Trigger {
+    Never,
+    Nth(usize),
+    Above(usize),
+    #[cfg(target_has_atomic = "64")]
+    Random(f64),
+}
+
+/// A schedule of allocation failures.
+///
+/// Only the operations selected with [`only`] (by default, all of them) are
+/// subject to failures, and count as calls for [`nth`].
+///
+/// The state of the schedule is kept in atomic counters, so a `Faults` can
+/// be shared between several `FailingAlloc`, which then fail as if they
+/// were one allocator.
+///
+/// [`only`]: #method.only
+/// [`nth`]: #method.nth
+pub struct Faults {
+    trigger: Trigger,
+    ops: Ops,
+    calls: AtomicUsize,
+    failures: AtomicUsize,
+    #[cfg(target_has_atomic = "64")]
+    rng: AtomicU64,
+}
+
+impl Faults {
+    const fn with_trigger(trigger: Trigger) -> Self {
+        Faults {
+            trigger,
+            ops: Ops::ALL,
+            calls: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+            #[cfg(target_has_atomic = "64")]
+            rng: AtomicU64::new(0),
+        }
+    }
+
+    /// A schedule where nothing fails.
+    pub const fn never() -> Self {
+        Faults::with_trigger(Trigger::Never)
+    }
+
+    /// A schedule where only the `n`th call (counting from 0) fails.
+    pub const fn nth(n: usize) -> Self {
+        Faults::with_trigger(Trigger::Nth(n))
+    }
+
+    /// A schedule where calls requesting more than `size` bytes fail.
+    pub const fn above(size: usize) -> Self {
+        Faults::with_trigger(Trigger::Above(size))
+    }
+
+    /// A schedule where calls fail with the given probability, using a
+    /// pseudo-random number generator initialized with `seed`.
+    ///
+    /// The same seed always yields the same sequence of failures.
+    ///
+    /// Only available on targets supporting 64-bit atomics.
+    #[cfg(target_has_atomic = "64")]
+    pub fn random(seed: u64, probability: f64) -> Self {
+        // xorshift can't get out of a zero state.
+        let seed = if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed };
+        Faults { rng: AtomicU64::new(seed), ..Faults::with_trigger(Trigger::Random(probability)) }
+    }
+
+    /// Restricts failures to the given operations.
+    pub fn only(mut self, ops: Ops) -> Self {
+        self.ops = ops;
+        self
+    }
+
+    /// Returns the number of calls subject to failures so far.
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of failures injected so far.
+    pub fn failures(&self) -> usize {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    fn should_fail(&self, ops: Ops, size: usize) -> bool {
+        if !self.ops.contains(ops) {
+            return false;
+        }
+        let call = self.calls.fetch_add(1, Ordering::Relaxed);
+        let fail = match self.trigger {
+            Trigger::Never => false,
+            Trigger::Nth(n) => call == n,
+            Trigger::Above(limit) => size > limit,
+            #[cfg(target_has_atomic = "64")]
+            Trigger::Random(probability) => {
+                // Each call gets its own step of the sequence, even when
+                // several threads use the schedule concurrently.
+                let x = self
+                    .rng
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| Some(xorshift(x)))
+                    .unwrap();
+                let x = xorshift(x).wrapping_mul(0x2545_f491_4f6c_dd1d);
+                ((x >> 11) as f64 / (1u64 << 53) as f64) < probability
+            }
+        };
+        if fail {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+        fail
+    }
+}
+
+/// The state transition of xorshift64*.
+#[cfg(target_has_atomic = "64")]
+fn xorshift(mut x: u64) -> u64 {
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    x
+}
+
+impl fmt::Debug for Faults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Faults")
+            .field("trigger", &self.trigger)
+            .field("ops", &self.ops)
+            .field("calls", &self.calls())
+            .field("failures", &self.failures())
+            .finish()
+    }
+}
+
+/// An allocator wrapper injecting failures according to a [`Faults`]
+/// schedule.
+///
+/// Calls that don't fail are forwarded to the wrapped allocator.
+///
+/// [`Faults`]: struct.Faults.html
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::testing::{FailingAlloc, Faults, Ops};
+/// use allocator_api::{Global, RawVec};
+///
+/// let faults = Faults::nth(1).only(Ops::REALLOC);
+/// let mut v: RawVec<u8, _> = RawVec::new_in(FailingAlloc::new(Global, &faults));
+/// assert!(v.try_reserve(0, 10).is_ok());
+/// assert!(v.try_reserve(10, 10).is_ok());
+/// assert!(v.try_reserve(20, 20).is_err());
+/// assert!(v.try_reserve(20, 20).is_ok());
+/// assert_eq!(faults.failures(), 1);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct FailingAlloc<'f, A> {
+    a: A,
+    faults: &'f Faults,
+}
+
+impl<'f, A: AllocRef> FailingAlloc<'f, A> {
+    /// Wraps the given allocator, failing according to `faults`.
+    pub fn new(a: A, faults: &'f Faults) -> Self {
+        FailingAlloc { a, faults }
+    }
+
+    /// Returns the failure schedule.
+    pub fn faults(&self) -> &'f Faults {
+        self.faults
+    }
+
+    /// Returns a reference to the wrapped allocator.
+    pub fn get_ref(&self) -> &A {
+        &self.a
+    }
+
+    /// Consumes the wrapper, returning the wrapped allocator.
+    pub fn into_inner(self) -> A {
+        self.a
+    }
+}
+
+unsafe impl<A: AllocRef> AllocRef for FailingAlloc<'_, A> {
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        if self.faults.should_fail(Ops::ALLOC, layout.size()) {
+            return Err(AllocErr);
+        }
+        self.a.alloc(layout)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.a.dealloc(ptr, layout)
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        self.a.usable_size(layout)
+    }
+
+    fn alloc_zeroed(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        if self.faults.should_fail(Ops::ALLOC, layout.size()) {
+            return Err(AllocErr);
+        }
+        self.a.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        if self.faults.should_fail(Ops::REALLOC, new_size) {
+            return Err(AllocErr);
+        }
+        self.a.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn realloc_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        if self.faults.should_fail(Ops::REALLOC, new_size) {
+            return Err(AllocErr);
+        }
+        self.a.realloc_zeroed(ptr, layout, new_size)
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        if self.faults.should_fail(Ops::GROW_IN_PLACE, new_size) {
+            return Err(CannotReallocInPlace);
+        }
+        self.a.grow_in_place(ptr, layout, new_size)
+    }
+
+    unsafe fn grow_in_place_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        if self.faults.should_fail(Ops::GROW_IN_PLACE, new_size) {
+            return Err(CannotReallocInPlace);
+        }
+        self.a.grow_in_place_zeroed(ptr, layout, new_size)
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        if self.faults.should_fail(Ops::SHRINK_IN_PLACE, new_size) {
+            return Err(CannotReallocInPlace);
+        }
+        self.a.shrink_in_place(ptr, layout, new_size)
+    }
+}
+
+/// Runs `f` repeatedly, making the first call subject to failures fail on
+/// the first run, the second call on the second run, and so on, until a run
+/// completes without any failure. Returns the number of runs.
+///
+/// The allocator given to `f` is wrapped in a [`LeakCheck`], so that leaks
+/// and double frees in the failure paths of `f` make this function panic.
+/// `f` is expected to use fallible allocation APIs; infallible ones don't
+/// return on failure.
+///
+/// [`LeakCheck`]: ../debug/struct.LeakCheck.html
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::testing::fail_each_call;
+/// use allocator_api::{Global, RawVec};
+///
+/// let runs = fail_each_call(Global, |a| {
+///     let mut v: RawVec<u32, _> = RawVec::new_in(a);
+///     for i in 0..4 {
+///         if v.try_reserve_exact(i * 8, 8).is_err() {
+///             return;
+///         }
+///     }
+/// });
+/// assert_eq!(runs, 5);
+/// # }
+/// ```
+#[cfg(feature = "std")]
+pub fn fail_each_call<A, F>(a: A, mut f: F) -> usize
+where
+    A: AllocRef + Clone,
+    F: FnMut(FailingAlloc<'_, LeakCheck<A>>),
+{
+    let mut runs = 0;
+    loop {
+        let faults = Faults::nth(runs);
+        let checked = LeakCheck::new(a.clone());
+        f(FailingAlloc::new(checked.clone(), &faults));
+        checked.assert_no_leaks();
+        runs += 1;
+        if faults.failures() == 0 {
+            return runs;
+        }
+    }
+}
+
+impl<A: Owns> Owns for FailingAlloc<'_, A> {
+    fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        self.a.owns(ptr, layout)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests;
@@ -0,0 +1,131 @@
+use super::*;
+use crate::alloc::Global;
+
+use std::sync::Arc;
+use std::thread;
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[test]
+fn faults_nth_fails_once() {
+    let faults = Faults::nth(2);
+    let mut a = FailingAlloc::new(Global, &faults);
+    let results: std::vec::Vec<_> = (0..4).map(|_| a.alloc(layout(8))).collect();
+    assert_eq!(results.iter().map(Result::is_ok).collect::<std::vec::Vec<_>>(), [true, true, false, true]);
+    assert_eq!((faults.calls(), faults.failures()), (4, 1));
+    for (ptr, _) in results.into_iter().flatten() {
+        unsafe { a.dealloc(ptr, layout(8)) };
+    }
+
+    // Operations that aren't selected don't count as calls.
+    let faults = Faults::nth(0).only(Ops::REALLOC | Ops::GROW_IN_PLACE);
+    let mut a = FailingAlloc::new(Global, &faults);
+    let (ptr, _) = a.alloc(layout(8)).unwrap();
+    assert_eq!(faults.calls(), 0);
+    unsafe {
+        assert_eq!(a.realloc(ptr, layout(8), 16), Err(AllocErr));
+        let (ptr, _) = a.realloc(ptr, layout(8), 16).unwrap();
+        a.dealloc(ptr, layout(16));
+    }
+    assert_eq!((faults.calls(), faults.failures()), (2, 1));
+}
+
+#[test]
+fn faults_above_fails_large_requests() {
+    let faults = Faults::above(64);
+    let mut a = FailingAlloc::new(Global, &faults);
+    assert_eq!(a.alloc(layout(65)), Err(AllocErr));
+    let (ptr, _) = a.alloc(layout(64)).unwrap();
+    unsafe {
+        assert_eq!(a.grow_in_place(ptr, layout(64), 128), Err(CannotReallocInPlace));
+        assert_eq!(a.realloc(ptr, layout(64), 128), Err(AllocErr));
+        let (ptr, _) = a.realloc(ptr, layout(64), 32).unwrap();
+        a.dealloc(ptr, layout(32));
+    }
+    assert_eq!((faults.calls(), faults.failures()), (5, 3));
+}
+
+#[cfg(target_has_atomic = "64")]
+#[test]
+fn faults_random_is_reproducible() {
+    fn run(faults: &Faults) -> std::vec::Vec<bool> {
+        (0..1000).map(|_| faults.should_fail(Ops::ALLOC, 0)).collect()
+    }
+
+    let sequence = run(&Faults::random(42, 0.25));
+    assert_eq!(run(&Faults::random(42, 0.25)), sequence);
+    // Neighbouring seeds give different sequences.
+    assert!(run(&Faults::random(43, 0.25)) != sequence);
+    assert!(run(&Faults::random(0, 0.25)) != run(&Faults::random(1, 0.25)));
+    let failures = sequence.iter().filter(|&&fail| fail).count();
+    assert!(failures > 150 && failures < 350, "{} failures", failures);
+    assert!(run(&Faults::random(42, 0.0)).iter().all(|&fail| !fail));
+    assert!(run(&Faults::random(42, 1.0)).iter().all(|&fail| fail));
+}
+
+#[cfg(target_has_atomic = "64")]
+#[test]
+fn faults_random_shared_between_threads() {
+    const THREADS: usize = 4;
+
+    // Threads sharing a schedule draw distinct steps of the same sequence,
+    // so the total number of failures is the same as on a single thread.
+    let expected = Faults::random(7, 0.5);
+    for _ in 0..THREADS * 1000 {
+        expected.should_fail(Ops::ALLOC, 0);
+    }
+    let faults = Arc::new(Faults::random(7, 0.5));
+    let handles: std::vec::Vec<_> = (0..THREADS)
+        .map(|_| {
+            let faults = faults.clone();
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    faults.should_fail(Ops::ALLOC, 0);
+                }
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert_eq!(faults.calls(), THREADS * 1000);
+    assert_eq!(faults.failures(), expected.failures());
+}
+
+#[test]
+fn fail_each_call_runs_until_success() {
+    let mut failed = std::vec::Vec::new();
+    let runs = fail_each_call(Global, |mut a| {
+        let mut blocks = std::vec::Vec::new();
+        for i in 0..3 {
+            match a.alloc(layout(8)) {
+                Ok((ptr, _)) => blocks.push(ptr),
+                Err(_) => failed.push(i),
+            }
+        }
+        for ptr in blocks {
+            unsafe { a.dealloc(ptr, layout(8)) };
+        }
+    });
+    assert_eq!(runs, 4);
+    assert_eq!(failed, [0, 1, 2]);
+}
+
+#[test]
+#[should_panic(expected = "1 allocation(s) leaked")]
+fn fail_each_call_detects_leaks_on_failure() {
+    fail_each_call(Global, |mut a| {
+        let first = a.alloc(layout(8));
+        let second = a.alloc(layout(8));
+        // Forgets to deallocate the first block when the second allocation
+        // fails.
+        if let (Ok((first, _)), Ok((second, _))) = (first, second) {
+            unsafe {
+                a.dealloc(first, layout(8));
+                a.dealloc(second, layout(8));
+            }
+        }
+    });
+}
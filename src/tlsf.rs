@@ -0,0 +1,510 @@
+//! Two-level segregated fit allocation.
+//!
+//! [`Tlsf`] allocates from caller-supplied memory pools in bounded time,
+//! which makes it suitable for real-time code: free blocks are kept in
+//! segregated lists indexed by two levels of bitmaps, so finding a block
+//! takes a couple of bit scans, and freed blocks are coalesced with their
+//! physical neighbours in constant time.
+//!
+//! [`Tlsf`]: struct.Tlsf.html
+
+use core::fmt;
+use core::mem;
+use core::ptr::NonNull;
+
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout};
+use crate::combinators::Owns;
+
+/// The header preceding each block.
+#[repr(C, align(16))]
+struct Header {
+    /// The previous block in the pool, if any.
+    prev_phys: Option<NonNull<Header>>,
+    /// The size of the block, not counting the header, with the `FREE` flag.
+    size: usize,
+}
+
+/// The links of a free block, stored in its payload.
+struct Links {
+    prev: Option<NonNull<Header>>,
+    next: Option<NonNull<Header>>,
+}
+
+/// The header at the start of each pool.
+#[repr(C, align(16))]
+struct Pool {
+    next: Option<NonNull<Pool>>,
+    end: usize,
+}
+
+const FREE: usize = 1;
+const HEADER: usize = mem::size_of::<Header>();
+const POOL: usize = mem::size_of::<Pool>();
+
+const ALIGN_LOG2: u32 = 4;
+const ALIGN: usize = 1 << ALIGN_LOG2;
+/// The smallest payload, large enough to hold the links of a free block.
+const MIN_SIZE: usize = ALIGN;
+
+/// Each power of two is divided in `SL_COUNT` second-level classes.
+const SL_LOG2: u32 = 4;
+const SL_COUNT: usize = 1 << SL_LOG2;
+/// Sizes below `SMALL` all have the first-level index 0, with second-level
+/// classes `ALIGN` bytes apart.
+const FL_SHIFT: u32 = SL_LOG2 + ALIGN_LOG2;
+const SMALL: usize = 1 << FL_SHIFT;
+const FL_COUNT: usize = (usize::BITS - FL_SHIFT + 1) as usize;
+
+/// Returns the size class of a block of the given size.
+fn mapping(size: usize) -> (usize, usize) {
+    if size < SMALL {
+        (0, size >> ALIGN_LOG2)
+    } else {
+        let log2 = usize::BITS - 1 - size.leading_zeros();
+        ((log2 - FL_SHIFT + 1) as usize, (size >> (log2 - SL_LOG2)) - SL_COUNT)
+    }
+}
+
+/// Rounds `size` up so that all the blocks in its size class are at least
+/// `size` bytes large.
+fn round_up_to_class(size: usize) -> Option<usize> {
+    if size < SMALL {
+        Some(size)
+    } else {
+        let log2 = usize::BITS - 1 - size.leading_zeros();
+        size.checked_add((1 << (log2 - SL_LOG2)) - 1)
+    }
+}
+
+/// Returns the payload size for a request of `size` bytes.
+fn adjust(size: usize) -> Option<usize> {
+    let size = size.checked_add(ALIGN - 1)? & !(ALIGN - 1);
+    Some(if size < MIN_SIZE { MIN_SIZE } else { size })
+}
+
+fn round_up(n: usize, align: usize) -> usize {
+    (n + align - 1) & !(align - 1)
+}
+
+unsafe fn size(b: NonNull<Header>) -> usize {
+    (*b.as_ptr()).size & !FREE
+}
+
+unsafe fn is_free(b: NonNull<Header>) -> bool {
+    (*b.as_ptr()).size & FREE != 0
+}
+
+unsafe fn payload(b: NonNull<Header>) -> NonNull<u8> {
+    NonNull::new_unchecked(b.as_ptr().cast::<u8>().add(HEADER))
+}
+
+unsafe fn header(ptr: NonNull<u8>) -> NonNull<Header> {
+    NonNull::new_unchecked(ptr.as_ptr().sub(HEADER).cast())
+}
+
+unsafe fn links(b: NonNull<Header>) -> *mut Links {
+    payload(b).as_ptr().cast()
+}
+
+unsafe fn next_phys(b: NonNull<Header>) -> NonNull<Header> {
+    NonNull::new_unchecked(payload(b).as_ptr().add(size(b)).cast())
+}
+
+/// Splits the block `b` after `size` bytes, returning the new block, which
+/// is not marked free.
+unsafe fn split(b: NonNull<Header>, size: usize) -> NonNull<Header> {
+    let rest = NonNull::new_unchecked(payload(b).as_ptr().add(size).cast::<Header>());
+    rest.as_ptr().write(Header {
+        prev_phys: Some(b),
+        size: self::size(b) - size - HEADER,
+    });
+    (*next_phys(rest).as_ptr()).prev_phys = Some(rest);
+    (*b.as_ptr()).size = size | ((*b.as_ptr()).size & FREE);
+    rest
+}
+
+/// Merges the block `b` with the block following it.
+unsafe fn merge(b: NonNull<Header>) {
+    let next = next_phys(b);
+    (*b.as_ptr()).size += HEADER + size(next);
+    (*next_phys(b).as_ptr()).prev_phys = Some(b);
+}
+
+/// A two-level segregated fit allocator.
+///
+/// `alloc`, `dealloc` and in-place reallocations run in bounded time, which
+/// doesn't depend on the number of blocks. Memory comes from pools given to
+/// [`add_pool`]. Each block is preceded by a 16-byte header, and sizes are
+/// rounded up to multiples of 16 bytes.
+///
+/// Blocks are split when they are larger than requested, and coalesced with
+/// their free neighbours when freed. `grow_in_place` succeeds when the block
+/// following the grown one is free and large enough.
+///
+/// [`add_pool`]: #method.add_pool
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::tlsf::Tlsf;
+/// use allocator_api::RawVec;
+/// use std::ptr::NonNull;
+///
+/// let mut pool = vec![0u8; 1 << 16];
+/// let mut tlsf = Tlsf::new();
+/// unsafe { tlsf.add_pool(NonNull::new(pool.as_mut_ptr()).unwrap(), pool.len()) };
+/// {
+///     let mut v: RawVec<u32, _> = RawVec::with_capacity_in(100, &mut tlsf);
+///     let ptr = v.ptr();
+///     v.reserve(100, 1000);
+///     assert_eq!(v.ptr(), ptr);
+/// }
+/// tlsf.check_integrity();
+/// # }
+/// ```
+pub struct Tlsf {
+    fl_bitmap: usize,
+    sl_bitmaps: [u32; FL_COUNT],
+    heads: [[Option<NonNull<Header>>; SL_COUNT]; FL_COUNT],
+    pools: Option<NonNull<Pool>>,
+}
+
+// The allocator owns its pools.
+unsafe impl Send for Tlsf {}
+
+impl Tlsf {
+    /// Creates an allocator without any pool.
+    pub const fn new() -> Self {
+        Tlsf {
+            fl_bitmap: 0,
+            sl_bitmaps: [0; FL_COUNT],
+            heads: [[None; SL_COUNT]; FL_COUNT],
+            pools: None,
+        }
+    }
+
+    /// Adds the `len` bytes starting at `pool` to the memory the allocator
+    /// allocates from.
+    ///
+    /// Part of the pool is used for bookkeeping: 16 bytes at the start, 16
+    /// bytes at the end, and whatever is needed to align both to 16 bytes.
+    ///
+    /// # Safety
+    ///
+    /// The pool must be valid for reads and writes, and not be used by
+    /// anything else, for as long as the allocator and the blocks it
+    /// allocated are used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool is too small to hold a block.
+    pub unsafe fn add_pool(&mut self, pool: NonNull<u8>, len: usize) {
+        let start = round_up(pool.as_ptr() as usize, ALIGN);
+        let end = (pool.as_ptr() as usize + len) & !(ALIGN - 1);
+        assert!(
+            end >= start && end - start >= POOL + 2 * HEADER + MIN_SIZE,
+            "pool too small"
+        );
+        let offset = start - pool.as_ptr() as usize;
+        let p = NonNull::new_unchecked(pool.as_ptr().add(offset).cast::<Pool>());
+        p.as_ptr().write(Pool { next: self.pools, end });
+        self.pools = Some(p);
+
+        let first = NonNull::new_unchecked(p.as_ptr().cast::<u8>().add(POOL).cast::<Header>());
+        first.as_ptr().write(Header {
+            prev_phys: None,
+            size: end - start - POOL - 2 * HEADER,
+        });
+        // A zero-sized block, never free, marks the end of the pool.
+        next_phys(first).as_ptr().write(Header { prev_phys: Some(first), size: 0 });
+        self.insert(first);
+    }
+
+    /// Walks all the blocks and free lists, checking the consistency of the
+    /// allocator's data structures.
+    ///
+    /// # Panics
+    ///
+    /// Panics when an inconsistency is found, which happens when blocks are
+    /// used out of their bounds.
+    pub fn check_integrity(&self) {
+        let mut free_blocks = 0;
+        let mut pool = self.pools;
+        while let Some(p) = pool {
+            unsafe {
+                let end = (*p.as_ptr()).end;
+                let mut prev = None;
+                let mut prev_free = false;
+                let mut b = NonNull::new_unchecked(p.as_ptr().cast::<u8>().add(POOL).cast::<Header>());
+                loop {
+                    assert!(
+                        (*b.as_ptr()).prev_phys == prev,
+                        "Heap corruption: the header of block {:p} was overwritten",
+                        payload(b)
+                    );
+                    let block_size = size(b);
+                    if block_size == 0 {
+                        assert!(
+                            b.as_ptr() as usize + HEADER == end,
+                            "Heap corruption: the header of block {:p} was overwritten",
+                            payload(b)
+                        );
+                        break;
+                    }
+                    assert!(
+                        block_size & (ALIGN - 1) == 0
+                            && block_size >= MIN_SIZE
+                            && payload(b).as_ptr() as usize + block_size < end,
+                        "Heap corruption: block {:p} has an invalid size {}",
+                        payload(b),
+                        block_size
+                    );
+                    if is_free(b) {
+                        assert!(
+                            !prev_free,
+                            "TLSF invariant violation: free block {:p} follows a free block",
+                            payload(b)
+                        );
+                        free_blocks += 1;
+                    }
+                    prev = Some(b);
+                    prev_free = is_free(b);
+                    b = next_phys(b);
+                }
+                pool = (*p.as_ptr()).next;
+            }
+        }
+
+        let mut listed_blocks = 0;
+        for fl in 0..FL_COUNT {
+            assert!(
+                (self.fl_bitmap >> fl & 1 != 0) == (self.sl_bitmaps[fl] != 0),
+                "TLSF invariant violation: first-level bitmap doesn't match list {}",
+                fl
+            );
+            for sl in 0..SL_COUNT {
+                assert!(
+                    (self.sl_bitmaps[fl] >> sl & 1 != 0) == self.heads[fl][sl].is_some(),
+                    "TLSF invariant violation: second-level bitmap doesn't match list ({}, {})",
+                    fl,
+                    sl
+                );
+                let mut prev = None;
+                let mut b = self.heads[fl][sl];
+                while let Some(block) = b {
+                    unsafe {
+                        assert!(
+                            is_free(block) && mapping(size(block)) == (fl, sl),
+                            "TLSF invariant violation: block {:p} doesn't belong in list ({}, {})",
+                            payload(block),
+                            fl,
+                            sl
+                        );
+                        assert!(
+                            (*links(block)).prev == prev,
+                            "Heap corruption: the links of free block {:p} were overwritten",
+                            payload(block)
+                        );
+                        prev = b;
+                        b = (*links(block)).next;
+                    }
+                    listed_blocks += 1;
+                }
+            }
+        }
+        assert!(
+            listed_blocks == free_blocks,
+            "TLSF invariant violation: {} free blocks, but {} in free lists",
+            free_blocks,
+            listed_blocks
+        );
+    }
+
+    /// Returns the first non-empty size class at least as large as the
+    /// given one.
+    fn find_suitable(&self, fl: usize, sl: usize) -> Option<(usize, usize)> {
+        if fl >= FL_COUNT {
+            return None;
+        }
+        let mut sl_map = self.sl_bitmaps[fl] & (!0 << sl);
+        let mut fl = fl;
+        if sl_map == 0 {
+            let fl_map = self.fl_bitmap & (!0usize).checked_shl(fl as u32 + 1).unwrap_or(0);
+            if fl_map == 0 {
+                return None;
+            }
+            fl = fl_map.trailing_zeros() as usize;
+            sl_map = self.sl_bitmaps[fl];
+        }
+        Some((fl, sl_map.trailing_zeros() as usize))
+    }
+
+    /// Marks the block free and adds it to its free list.
+    unsafe fn insert(&mut self, b: NonNull<Header>) {
+        let (fl, sl) = mapping(size(b));
+        let next = self.heads[fl][sl];
+        links(b).write(Links { prev: None, next });
+        if let Some(next) = next {
+            (*links(next)).prev = Some(b);
+        }
+        self.heads[fl][sl] = Some(b);
+        self.sl_bitmaps[fl] |= 1 << sl;
+        self.fl_bitmap |= 1 << fl;
+        (*b.as_ptr()).size |= FREE;
+    }
+
+    /// Removes the block from its free list and marks it used.
+    unsafe fn remove(&mut self, b: NonNull<Header>) {
+        let (fl, sl) = mapping(size(b));
+        let Links { prev, next } = links(b).read();
+        match prev {
+            Some(prev) => (*links(prev)).next = next,
+            None => self.heads[fl][sl] = next,
+        }
+        if let Some(next) = next {
+            (*links(next)).prev = prev;
+        }
+        if self.heads[fl][sl].is_none() {
+            self.sl_bitmaps[fl] &= !(1 << sl);
+            if self.sl_bitmaps[fl] == 0 {
+                self.fl_bitmap &= !(1 << fl);
+            }
+        }
+        (*b.as_ptr()).size &= !FREE;
+    }
+
+    /// Frees the block, coalescing it with its free neighbours.
+    unsafe fn release(&mut self, mut b: NonNull<Header>) {
+        let next = next_phys(b);
+        if is_free(next) {
+            self.remove(next);
+            merge(b);
+        }
+        if let Some(prev) = (*b.as_ptr()).prev_phys {
+            if is_free(prev) {
+                self.remove(prev);
+                merge(prev);
+                b = prev;
+            }
+        }
+        self.insert(b);
+    }
+}
+
+impl Default for Tlsf {
+    fn default() -> Self {
+        Tlsf::new()
+    }
+}
+
+impl fmt::Debug for Tlsf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tlsf")
+            .field("fl_bitmap", &format_args!("{:#x}", self.fl_bitmap))
+            .finish()
+    }
+}
+
+unsafe impl AllocRef for Tlsf {
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let need = adjust(layout.size()).ok_or(AllocErr)?;
+        // Make room to align the payload, leaving enough space before it for
+        // a free block.
+        let search = if layout.align() <= ALIGN {
+            need
+        } else {
+            need.checked_add(layout.align() + HEADER + MIN_SIZE).ok_or(AllocErr)?
+        };
+        let (fl, sl) = mapping(round_up_to_class(search).ok_or(AllocErr)?);
+        let (fl, sl) = self.find_suitable(fl, sl).ok_or(AllocErr)?;
+        unsafe {
+            let mut b = self.heads[fl][sl].unwrap();
+            self.remove(b);
+            if layout.align() > ALIGN {
+                let start = payload(b).as_ptr() as usize;
+                let mut aligned = round_up(start, layout.align());
+                if aligned != start && aligned - start < HEADER + MIN_SIZE {
+                    aligned = round_up(start + HEADER + MIN_SIZE, layout.align());
+                }
+                if aligned != start {
+                    let front = b;
+                    b = split(front, aligned - start - HEADER);
+                    self.insert(front);
+                }
+            }
+            if size(b) >= need + HEADER + MIN_SIZE {
+                let rest = split(b, need);
+                self.insert(rest);
+            }
+            Ok((payload(b), size(b)))
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, _layout: Layout) {
+        self.release(header(ptr))
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        // Blocks are at least the adjusted size, and `dealloc` ignores the size.
+        (layout.size(), adjust(layout.size()).unwrap_or(layout.size()))
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        _layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let need = adjust(new_size).ok_or(CannotReallocInPlace)?;
+        let b = header(ptr);
+        if need <= size(b) {
+            return Ok(size(b));
+        }
+        let next = next_phys(b);
+        if !is_free(next) || size(b) + HEADER + size(next) < need {
+            return Err(CannotReallocInPlace);
+        }
+        self.remove(next);
+        merge(b);
+        if size(b) >= need + HEADER + MIN_SIZE {
+            let rest = split(b, need);
+            self.insert(rest);
+        }
+        Ok(size(b))
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        _layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let need = adjust(new_size).ok_or(CannotReallocInPlace)?;
+        let b = header(ptr);
+        if size(b) >= need + HEADER + MIN_SIZE {
+            let rest = split(b, need);
+            self.release(rest);
+        }
+        Ok(size(b))
+    }
+}
+
+impl Owns for Tlsf {
+    fn owns(&self, ptr: NonNull<u8>, _layout: Layout) -> bool {
+        let ptr = ptr.as_ptr() as usize;
+        let mut pool = self.pools;
+        while let Some(p) = pool {
+            let (start, end, next) = unsafe { (p.as_ptr() as usize, (*p.as_ptr()).end, (*p.as_ptr()).next) };
+            if (start..end).contains(&ptr) {
+                return true;
+            }
+            pool = next;
+        }
+        false
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests;
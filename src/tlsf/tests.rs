@@ -0,0 +1,95 @@
+use super::*;
+use std::vec::Vec;
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[test]
+fn tlsf_size_classes() {
+    assert_eq!(mapping(16), (0, 1));
+    assert_eq!(mapping(SMALL - ALIGN), (0, SL_COUNT - 1));
+    assert_eq!(mapping(SMALL), (1, 0));
+    assert_eq!(mapping(SMALL * 2 - 1), (1, SL_COUNT - 1));
+    assert_eq!(mapping(1000), (2, 15));
+    assert_eq!(mapping(round_up_to_class(1000).unwrap()), (3, 0));
+    assert_eq!(mapping(usize::MAX), (FL_COUNT - 1, SL_COUNT - 1));
+}
+
+#[test]
+fn tlsf_splits_and_coalesces() {
+    let mut pool = std::vec![0u8; 1 << 16];
+    let mut tlsf = Tlsf::new();
+    unsafe { tlsf.add_pool(NonNull::new(pool.as_mut_ptr()).unwrap(), pool.len()) };
+    tlsf.check_integrity();
+
+    let blocks: Vec<_> = (1..50).map(|i| tlsf.alloc(layout(i * 24)).unwrap()).collect();
+    tlsf.check_integrity();
+    for &(ptr, size) in &blocks {
+        assert_eq!(ptr.as_ptr() as usize % ALIGN, 0);
+        assert!(tlsf.owns(ptr, layout(size)));
+    }
+    assert_eq!(blocks[0].1, 32);
+    // Free every other block, then the rest, so that blocks are coalesced
+    // with both neighbours.
+    for &(ptr, size) in blocks.iter().step_by(2) {
+        unsafe { tlsf.dealloc(ptr, layout(size)) };
+    }
+    tlsf.check_integrity();
+    for &(ptr, size) in blocks.iter().skip(1).step_by(2) {
+        unsafe { tlsf.dealloc(ptr, layout(size)) };
+    }
+    tlsf.check_integrity();
+
+    // Everything was coalesced back in a single block.
+    let all = pool.len() - POOL - 2 * HEADER;
+    let (fl, sl) = mapping(all);
+    assert_eq!(tlsf.fl_bitmap, 1 << fl);
+    assert_eq!(tlsf.sl_bitmaps[fl], 1 << sl);
+    assert_eq!(unsafe { size(tlsf.heads[fl][sl].unwrap()) }, all);
+    // Good fit: requests are rounded up to the next size class.
+    assert_eq!(tlsf.alloc(layout(all)), Err(AllocErr));
+}
+
+#[test]
+fn tlsf_aligns_and_resizes() {
+    let mut pool = std::vec![0u8; 1 << 16];
+    let mut tlsf = Tlsf::new();
+    unsafe { tlsf.add_pool(NonNull::new(pool.as_mut_ptr()).unwrap(), pool.len()) };
+    unsafe {
+        let (a, _) = tlsf.alloc(layout(100)).unwrap();
+        let (b, _) = tlsf.alloc(Layout::from_size_align(100, 1024).unwrap()).unwrap();
+        assert_eq!(b.as_ptr() as usize % 1024, 0);
+        tlsf.check_integrity();
+
+        // `a` can't grow, because it is followed by the block that was split
+        // off to align `b`, which is too small.
+        assert_eq!(tlsf.grow_in_place(a, layout(100), 2000), Err(CannotReallocInPlace));
+        assert!(tlsf.grow_in_place(b, layout(100), 2000).unwrap() >= 2000);
+        assert_eq!(tlsf.shrink_in_place(b, layout(2000), 50), Ok(64));
+        tlsf.check_integrity();
+
+        a.as_ptr().write_bytes(0x42, 100);
+        let (c, size) = tlsf.realloc(a, layout(100), 2000).unwrap();
+        assert_ne!(c, a);
+        assert!(size >= 2000);
+        assert_eq!(*c.as_ptr().add(99), 0x42);
+        tlsf.check_integrity();
+
+        tlsf.dealloc(b, layout(50));
+        tlsf.dealloc(c, layout(2000));
+        tlsf.check_integrity();
+    }
+}
+
+#[test]
+#[should_panic(expected = "Heap corruption")]
+fn tlsf_detects_corruption() {
+    let mut pool = std::vec![0u8; 1 << 12];
+    let mut tlsf = Tlsf::new();
+    unsafe { tlsf.add_pool(NonNull::new(pool.as_mut_ptr()).unwrap(), pool.len()) };
+    let (a, _) = tlsf.alloc(layout(32)).unwrap();
+    // Overflow the block into the header of the next one.
+    unsafe { a.as_ptr().write_bytes(0xff, 48) };
+    tlsf.check_integrity();
+}
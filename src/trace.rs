@@ -0,0 +1,632 @@
+//! Allocation tracing.
+//!
+//! [`Tracing`] wraps any `AllocRef` and emits an [`Event`] for every call
+//! going through it to a [`TraceSink`]. Under the `std` feature, traces can
+//! be recorded to a compact binary format with [`TraceWriter`], read back
+//! with [`TraceReader`], and [`replay`]ed against another allocator to
+//! compare its behavior on a real workload.
+//!
+//! [`Tracing`]: struct.Tracing.html
+//! [`Event`]: struct.Event.html
+//! [`TraceSink`]: trait.TraceSink.html
+//! [`TraceWriter`]: struct.TraceWriter.html
+//! [`TraceReader`]: struct.TraceReader.html
+//! [`replay`]: fn.replay.html
+
+use core::ptr::NonNull;
+
+use crate::alloc::{AllocErr, AllocRef, CannotReallocInPlace, Layout};
+use crate::combinators::Owns;
+
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+#[cfg(feature = "std")]
+use crate::stats::{Counting, Snapshot, Stats};
+
+/// An `AllocRef` operation.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Op {
+    /// `alloc`.
+    Alloc,
+    /// `alloc_zeroed`.
+    AllocZeroed,
+    /// `dealloc`.
+    Dealloc,
+    /// `realloc`.
+    Realloc,
+    /// `realloc_zeroed`.
+    ReallocZeroed,
+    /// `grow_in_place`.
+    GrowInPlace,
+    /// `grow_in_place_zeroed`.
+    GrowInPlaceZeroed,
+    /// `shrink_in_place`.
+    ShrinkInPlace,
+}
+
+#[cfg(feature = "std")]
+impl Op {
+    const ALL: [Op; 8] = [
+        Op::Alloc,
+        Op::AllocZeroed,
+        Op::Dealloc,
+        Op::Realloc,
+        Op::ReallocZeroed,
+        Op::GrowInPlace,
+        Op::GrowInPlaceZeroed,
+        Op::ShrinkInPlace,
+    ];
+
+    fn is_alloc(self) -> bool {
+        matches!(self, Op::Alloc | Op::AllocZeroed)
+    }
+
+    fn is_resize(self) -> bool {
+        !matches!(self, Op::Alloc | Op::AllocZeroed | Op::Dealloc)
+    }
+
+    fn is_in_place(self) -> bool {
+        matches!(self, Op::GrowInPlace | Op::GrowInPlaceZeroed | Op::ShrinkInPlace)
+    }
+}
+
+/// A call to an `AllocRef` method.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Event {
+    /// The method called.
+    pub op: Op,
+    /// The pointer given to the method, or 0 for `alloc` and
+    /// `alloc_zeroed`.
+    pub ptr: usize,
+    /// The layout given to the method.
+    pub layout: Layout,
+    /// The new size given to reallocation methods. Equal to
+    /// `layout.size()` for other methods.
+    pub new_size: usize,
+    /// The address and size returned by the method, or `None` if it failed.
+    /// For in-place methods, the address is `ptr`. For `dealloc`, this is
+    /// always `None`.
+    pub result: Option<(usize, usize)>,
+    /// Time of the call, in nanoseconds since the [`Tracing`] allocator was
+    /// created. Always 0 without the `std` feature.
+    ///
+    /// [`Tracing`]: struct.Tracing.html
+    pub timestamp: u64,
+    /// Identifier of the calling thread, unique for the lifetime of the
+    /// process. Always 0 without the `std` feature.
+    pub thread: u64,
+}
+
+/// A receiver of [`Event`]s.
+///
+/// Under the `std` feature, `Vec<Event>` is a sink collecting events, and
+/// `&Mutex<S>` allows several `Tracing` allocators to share a sink.
+///
+/// [`Event`]: struct.Event.html
+pub trait TraceSink {
+    /// Receives an event.
+    fn event(&mut self, event: &Event);
+}
+
+impl<S: TraceSink + ?Sized> TraceSink for &mut S {
+    #[inline]
+    fn event(&mut self, event: &Event) {
+        (**self).event(event)
+    }
+}
+
+#[cfg(feature = "std")]
+impl TraceSink for std::vec::Vec<Event> {
+    fn event(&mut self, event: &Event) {
+        self.push(*event)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: TraceSink + ?Sized> TraceSink for &Mutex<S> {
+    fn event(&mut self, event: &Event) {
+        self.lock().unwrap_or_else(|e| e.into_inner()).event(event)
+    }
+}
+
+#[cfg(feature = "std")]
+fn thread_id() -> u64 {
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    std::thread_local!(static ID: u64 = NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    // The thread local is unavailable while the thread is being torn down.
+    ID.try_with(|id| *id).unwrap_or(0)
+}
+
+/// An allocator wrapper emitting an [`Event`] to a [`TraceSink`] for every
+/// call.
+///
+/// Events are emitted after the call to the wrapped allocator returns. The
+/// sink must not allocate through the traced allocator, which matters when
+/// tracing the global allocator.
+///
+/// [`Event`]: struct.Event.html
+/// [`TraceSink`]: trait.TraceSink.html
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::trace::{Op, Tracing};
+/// use allocator_api::{Box, Global};
+///
+/// let mut events = Vec::new();
+/// drop(Box::new_in(42u32, Tracing::new(Global, &mut events)));
+/// assert_eq!(events.len(), 2);
+/// assert_eq!(events[0].op, Op::Alloc);
+/// assert_eq!(events[1].op, Op::Dealloc);
+/// assert_eq!(events[1].ptr, events[0].result.unwrap().0);
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Tracing<A, S> {
+    a: A,
+    sink: S,
+    #[cfg(feature = "std")]
+    start: Instant,
+}
+
+impl<A: AllocRef, S: TraceSink> Tracing<A, S> {
+    /// Wraps the given allocator, emitting events to `sink`.
+    pub fn new(a: A, sink: S) -> Self {
+        Tracing {
+            a,
+            sink,
+            #[cfg(feature = "std")]
+            start: Instant::now(),
+        }
+    }
+
+    /// Returns a reference to the sink.
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Returns a reference to the wrapped allocator.
+    pub fn get_ref(&self) -> &A {
+        &self.a
+    }
+
+    /// Consumes the wrapper, returning the wrapped allocator and the sink.
+    pub fn into_parts(self) -> (A, S) {
+        (self.a, self.sink)
+    }
+
+    fn emit(
+        &mut self,
+        op: Op,
+        ptr: Option<NonNull<u8>>,
+        layout: Layout,
+        new_size: usize,
+        result: Option<(NonNull<u8>, usize)>,
+    ) {
+        #[cfg(feature = "std")]
+        let (timestamp, thread) = (self.start.elapsed().as_nanos() as u64, thread_id());
+        #[cfg(not(feature = "std"))]
+        let (timestamp, thread) = (0, 0);
+        self.sink.event(&Event {
+            op,
+            ptr: ptr.map_or(0, |p| p.as_ptr() as usize),
+            layout,
+            new_size,
+            result: result.map(|(p, size)| (p.as_ptr() as usize, size)),
+            timestamp,
+            thread,
+        });
+    }
+
+    fn alloc_impl(&mut self, op: Op, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let result = match op {
+            Op::AllocZeroed => self.a.alloc_zeroed(layout),
+            _ => self.a.alloc(layout),
+        };
+        self.emit(op, None, layout, layout.size(), result.as_ref().ok().copied());
+        result
+    }
+
+    unsafe fn realloc_impl(
+        &mut self,
+        op: Op,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        let result = match op {
+            Op::ReallocZeroed => self.a.realloc_zeroed(ptr, layout, new_size),
+            _ => self.a.realloc(ptr, layout, new_size),
+        };
+        self.emit(op, Some(ptr), layout, new_size, result.as_ref().ok().copied());
+        result
+    }
+
+    unsafe fn in_place_impl(
+        &mut self,
+        op: Op,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        let result = match op {
+            Op::GrowInPlaceZeroed => self.a.grow_in_place_zeroed(ptr, layout, new_size),
+            Op::ShrinkInPlace => self.a.shrink_in_place(ptr, layout, new_size),
+            _ => self.a.grow_in_place(ptr, layout, new_size),
+        };
+        self.emit(op, Some(ptr), layout, new_size, result.as_ref().ok().map(|&size| (ptr, size)));
+        result
+    }
+}
+
+unsafe impl<A: AllocRef, S: TraceSink> AllocRef for Tracing<A, S> {
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.alloc_impl(Op::Alloc, layout)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.a.dealloc(ptr, layout);
+        self.emit(Op::Dealloc, Some(ptr), layout, layout.size(), None);
+    }
+
+    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
+        self.a.usable_size(layout)
+    }
+
+    fn alloc_zeroed(&mut self, layout: Layout) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.alloc_impl(Op::AllocZeroed, layout)
+    }
+
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.realloc_impl(Op::Realloc, ptr, layout, new_size)
+    }
+
+    unsafe fn realloc_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocErr> {
+        self.realloc_impl(Op::ReallocZeroed, ptr, layout, new_size)
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.in_place_impl(Op::GrowInPlace, ptr, layout, new_size)
+    }
+
+    unsafe fn grow_in_place_zeroed(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.in_place_impl(Op::GrowInPlaceZeroed, ptr, layout, new_size)
+    }
+
+    unsafe fn shrink_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<usize, CannotReallocInPlace> {
+        self.in_place_impl(Op::ShrinkInPlace, ptr, layout, new_size)
+    }
+}
+
+impl<A: Owns, S> Owns for Tracing<A, S> {
+    fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        self.a.owns(ptr, layout)
+    }
+}
+
+#[cfg(feature = "std")]
+const MAGIC: &[u8; 4] = b"ATRC";
+#[cfg(feature = "std")]
+const VERSION: u8 = 1;
+#[cfg(feature = "std")]
+const RESULT_FLAG: u8 = 0x80;
+
+/// A [`TraceSink`] writing events in a compact binary format.
+///
+/// The format starts with a 5 bytes header, followed by one record per
+/// event. Each record starts with a byte holding the operation, and whether
+/// the call succeeded, followed by the other fields of the event encoded as
+/// LEB128 variable-length integers. Timestamps are stored as a difference
+/// from the previous record, and fields that can be inferred from the
+/// operation are omitted.
+///
+/// Writes are not buffered; wrap the writer in a `BufWriter` when writing
+/// to a file. Since sinks can't report errors, the first I/O error is kept
+/// and returned by [`finish`], and further events are dropped.
+///
+/// [`TraceSink`]: trait.TraceSink.html
+/// [`finish`]: #method.finish
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate allocator_api;
+/// # test_using_global! {
+/// use allocator_api::trace::{replay, TraceReader, TraceWriter, Tracing};
+/// use allocator_api::{Global, RawVec};
+///
+/// let mut writer = TraceWriter::new(Vec::new()).unwrap();
+/// let mut v: RawVec<u8, _> = RawVec::with_capacity_in(16, Tracing::new(Global, &mut writer));
+/// v.reserve(16, 48);
+/// drop(v);
+/// let trace = writer.finish().unwrap();
+///
+/// let events = TraceReader::new(&trace[..]).unwrap().map(Result::unwrap);
+/// let snapshot = replay(Global, events);
+/// assert_eq!(snapshot.allocs, 1);
+/// assert_eq!(snapshot.reallocs, 1);
+/// assert_eq!(snapshot.deallocs, 1);
+/// assert_eq!(snapshot.peak_bytes, 64);
+/// # }
+/// ```
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct TraceWriter<W: Write> {
+    w: W,
+    timestamp: u64,
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> TraceWriter<W> {
+    /// Creates a new writer, writing the trace header to `w`.
+    pub fn new(mut w: W) -> io::Result<Self> {
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION])?;
+        Ok(TraceWriter { w, timestamp: 0, error: None })
+    }
+
+    /// Flushes the underlying writer and returns it, or the first error
+    /// that happened while writing events.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.w.flush()?;
+        Ok(self.w)
+    }
+
+    fn write_event(&mut self, event: &Event) -> io::Result<()> {
+        let mut buf = [0u8; 1 + 8 * 10];
+        let op = Op::ALL.iter().position(|&op| op == event.op).unwrap() as u8;
+        buf[0] = op | if event.result.is_some() { RESULT_FLAG } else { 0 };
+        let mut len = 1;
+        let mut put = |value: u64| {
+            let mut value = value;
+            loop {
+                let byte = (value & 0x7f) as u8;
+                value >>= 7;
+                if value == 0 {
+                    buf[len] = byte;
+                    len += 1;
+                    return;
+                }
+                buf[len] = byte | 0x80;
+                len += 1;
+            }
+        };
+        put(u64::from(event.layout.align().trailing_zeros()));
+        put(event.thread);
+        // Events from different threads may be emitted out of order.
+        let delta = event.timestamp.wrapping_sub(self.timestamp) as i64;
+        put(((delta << 1) ^ (delta >> 63)) as u64);
+        self.timestamp = event.timestamp;
+        if !event.op.is_alloc() {
+            put(event.ptr as u64);
+        }
+        put(event.layout.size() as u64);
+        if event.op.is_resize() {
+            put(event.new_size as u64);
+        }
+        if let Some((ptr, size)) = event.result {
+            if !event.op.is_in_place() {
+                put(ptr as u64);
+            }
+            put(size as u64);
+        }
+        self.w.write_all(&buf[..len])
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> TraceSink for TraceWriter<W> {
+    fn event(&mut self, event: &Event) {
+        if self.error.is_none() {
+            if let Err(e) = self.write_event(event) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+/// An iterator over the events of a trace written by a [`TraceWriter`].
+///
+/// [`TraceWriter`]: struct.TraceWriter.html
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct TraceReader<R: Read> {
+    r: R,
+    timestamp: u64,
+}
+
+#[cfg(feature = "std")]
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> TraceReader<R> {
+    /// Creates a new reader, checking the trace header from `r`.
+    pub fn new(mut r: R) -> io::Result<Self> {
+        let mut header = [0; 5];
+        r.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid_data("not an allocation trace"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid_data("unsupported allocation trace version"));
+        }
+        Ok(TraceReader { r, timestamp: 0 })
+    }
+
+    fn get(&mut self) -> io::Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0];
+            self.r.read_exact(&mut byte)?;
+            value |= u64::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid_data("integer overflow in allocation trace"))
+    }
+
+    fn read_event(&mut self, first: u8) -> io::Result<Event> {
+        let op = *Op::ALL
+            .get(usize::from(first & !RESULT_FLAG))
+            .ok_or_else(|| invalid_data("invalid operation in allocation trace"))?;
+        let align = 1usize
+            .checked_shl(self.get()? as u32)
+            .ok_or_else(|| invalid_data("invalid alignment in allocation trace"))?;
+        let thread = self.get()?;
+        let delta = self.get()?;
+        let delta = ((delta >> 1) as i64) ^ -((delta & 1) as i64);
+        self.timestamp = self.timestamp.wrapping_add(delta as u64);
+        let ptr = if op.is_alloc() { 0 } else { self.get()? as usize };
+        let size = self.get()? as usize;
+        let layout = Layout::from_size_align(size, align)
+            .map_err(|_| invalid_data("invalid layout in allocation trace"))?;
+        let new_size = if op.is_resize() { self.get()? as usize } else { size };
+        let result = if first & RESULT_FLAG != 0 {
+            let result_ptr = if op.is_in_place() { ptr } else { self.get()? as usize };
+            Some((result_ptr, self.get()? as usize))
+        } else {
+            None
+        };
+        Ok(Event { op, ptr, layout, new_size, result, timestamp: self.timestamp, thread })
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<io::Result<Event>> {
+        let mut first = [0];
+        match self.r.read(&mut first) {
+            Ok(0) => None,
+            Ok(_) => Some(self.read_event(first[0]).map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => invalid_data("truncated allocation trace"),
+                _ => e,
+            })),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Replays a trace against the given allocator, and returns the statistics
+/// of the calls it received.
+///
+/// Calls that failed when the trace was recorded are skipped. When an
+/// in-place reallocation that succeeded in the trace fails with `a`, it is
+/// replaced with a `realloc`. When a call fails with `a`, the block stays
+/// as it was, and further calls on it go on with its current layout.
+///
+/// Blocks still live at the end of the trace are deallocated after the
+/// statistics are taken.
+#[cfg(feature = "std")]
+pub fn replay<A, I>(a: A, trace: I) -> Snapshot
+where
+    A: AllocRef,
+    I: IntoIterator<Item = Event>,
+{
+    let stats = Stats::new();
+    let mut a = Counting::new(a, &stats);
+    // Maps addresses in the trace to the blocks allocated by `a`.
+    let mut blocks: HashMap<usize, (NonNull<u8>, Layout)> = HashMap::new();
+    for event in trace {
+        let result_ptr = match (event.op, event.result) {
+            (Op::Dealloc, _) => 0,
+            (_, Some((ptr, _))) => ptr,
+            (_, None) => continue,
+        };
+        if event.op.is_alloc() {
+            let result = match event.op {
+                Op::AllocZeroed => a.alloc_zeroed(event.layout),
+                _ => a.alloc(event.layout),
+            };
+            if let Ok((ptr, _)) = result {
+                blocks.insert(result_ptr, (ptr, event.layout));
+            }
+            continue;
+        }
+        let (ptr, layout) = match blocks.remove(&event.ptr) {
+            Some(block) => block,
+            None => continue,
+        };
+        let new_layout = unsafe { Layout::from_size_align_unchecked(event.new_size, layout.align()) };
+        let new_ptr = unsafe {
+            let in_place = match event.op {
+                Op::Dealloc => {
+                    a.dealloc(ptr, layout);
+                    continue;
+                }
+                Op::GrowInPlace => a.grow_in_place(ptr, layout, event.new_size).is_ok(),
+                Op::GrowInPlaceZeroed => a.grow_in_place_zeroed(ptr, layout, event.new_size).is_ok(),
+                Op::ShrinkInPlace => a.shrink_in_place(ptr, layout, event.new_size).is_ok(),
+                _ => false,
+            };
+            if in_place {
+                Ok(ptr)
+            } else {
+                match event.op {
+                    Op::ReallocZeroed | Op::GrowInPlaceZeroed => {
+                        a.realloc_zeroed(ptr, layout, event.new_size)
+                    }
+                    _ => a.realloc(ptr, layout, event.new_size),
+                }
+                .map(|(ptr, _)| ptr)
+            }
+        };
+        let block = match new_ptr {
+            Ok(new_ptr) => (new_ptr, new_layout),
+            Err(_) => (ptr, layout),
+        };
+        blocks.insert(result_ptr, block);
+    }
+    let snapshot = stats.snapshot();
+    for (_, (ptr, layout)) in blocks {
+        unsafe { a.dealloc(ptr, layout) };
+    }
+    snapshot
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests;
@@ -0,0 +1,71 @@
+use super::*;
+use crate::alloc::Global;
+use crate::raw_vec::RawVec;
+use std::vec;
+use std::vec::Vec;
+
+#[test]
+fn trace_round_trip() {
+    let events = Mutex::new(Vec::new());
+    {
+        let mut v: RawVec<u64, _> = RawVec::with_capacity_in(4, Tracing::new(Global, &events));
+        v.reserve(4, 60);
+        v.shrink_to_fit(2);
+        let mut a = Tracing::new(Global, &events);
+        let layout = Layout::from_size_align(8, 32).unwrap();
+        let (ptr, _) = a.alloc_zeroed(layout).unwrap();
+        unsafe { a.dealloc(ptr, layout) };
+    }
+    let mut events = events.into_inner().unwrap();
+    let failed = Event {
+        op: Op::GrowInPlace,
+        ptr: 0x1000,
+        layout: Layout::from_size_align(16, 8).unwrap(),
+        new_size: 32,
+        result: None,
+        timestamp: 0,
+        thread: 7,
+    };
+    events.push(failed);
+
+    let mut writer = TraceWriter::new(Vec::new()).unwrap();
+    for event in &events {
+        writer.event(event);
+    }
+    let trace = writer.finish().unwrap();
+    let read: Vec<Event> = TraceReader::new(&trace[..]).unwrap().collect::<io::Result<_>>().unwrap();
+    assert_eq!(read, events);
+
+    assert_eq!(
+        TraceReader::new(&trace[..trace.len() - 1]).unwrap().last().unwrap().unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+    assert!(TraceReader::new(&b"ATRC\x02"[..]).is_err());
+}
+
+#[test]
+fn replay_falls_back_from_in_place() {
+    let event = |op, ptr, size, new_size, result| Event {
+        op,
+        ptr,
+        layout: Layout::from_size_align(size, 8).unwrap(),
+        new_size,
+        result,
+        timestamp: 0,
+        thread: 1,
+    };
+    let trace = vec![
+        event(Op::Alloc, 0, 16, 16, Some((0x1000, 16))),
+        event(Op::Alloc, 0, 16, 16, None),
+        event(Op::GrowInPlace, 0x1000, 16, 4096, Some((0x1000, 4096))),
+        event(Op::Alloc, 0, 32, 32, Some((0x2000, 32))),
+        event(Op::Dealloc, 0x1000, 4096, 4096, None),
+    ];
+    let snapshot = replay(Global, trace);
+    assert_eq!(snapshot.allocs, 2);
+    assert_eq!(snapshot.failed_allocs, 0);
+    assert_eq!(snapshot.reallocs, 1);
+    assert_eq!(snapshot.deallocs, 1);
+    assert_eq!(snapshot.live_bytes, 32);
+    assert_eq!(snapshot.peak_bytes, 4096 + 32);
+}